@@ -0,0 +1,340 @@
+//! The `unlambda` command line interpreter. Run with `--help` for usage.
+
+use std::{
+    ffi::OsString,
+    io::{BufRead, Read, Write},
+    path::PathBuf,
+    process::ExitCode,
+};
+use unlambda::{
+    io::Ctx,
+    parse::{ParseError, ParseErrorKind, ParseOptions},
+    repl::{Repl, Reply},
+    transcript::Transcript,
+    Encoding, EvalError, EvalOptions, Input, Utf8Policy,
+};
+
+const USAGE: &str = "\
+Usage: unlambda [OPTIONS] [PROGRAM]
+       unlambda --repl [OPTIONS]
+
+Runs the unlambda program in the file PROGRAM. If PROGRAM is `-` or missing,
+the program is read from stdin (in which case it's probably a good idea to
+provide the program's input with `--input` or `--input-str`).
+
+With `--repl`, reads expressions from stdin a line at a time and prints the
+value of each. `$name = <expr>` defines `name`, which can then be used as
+`$name` in later expressions. Since stdin is taken, expressions that read
+input fail unless it's given with `--input` or `--input-str`.
+
+Options:
+  -e, --eval <SOURCE>       Run SOURCE instead of reading a program file
+  -i, --input <FILE>        Read the program's input from FILE
+  -s, --input-str <TEXT>    Use TEXT as the program's input
+      --strict              Reject trailing garbage after the program
+      --max-steps <N>       Stop the program after N evaluation steps
+      --max-objects <N>     Stop the program if it has N objects in memory
+      --max-output <N>      Stop the program if it prints more than N characters
+      --timeout <SECONDS>   Stop the program after it runs for SECONDS
+      --latin1              Do IO a byte at a time instead of as UTF-8
+      --invalid-utf8 <HOW>  What to do with input that isn't UTF-8: `replace`
+                            it with U+FFFD (the default), `skip` it, or stop
+                            with an `error`
+      --record <FILE>       Write a transcript of the program's IO to FILE
+      --replay <FILE>       Feed the program the input in the transcript FILE,
+                            and check it prints what the transcript says
+  -r, --repl                Start an interactive read-eval-print loop
+  -h, --help                Print this message
+
+The program's input defaults to stdin (except with `--repl`).
+
+Exit status:
+  0   the program terminated normally
+  1   the program was stopped early by one of the limits above
+  64  bad command line arguments
+  65  the program failed to parse, or its input wasn't UTF-8 (see
+      `--invalid-utf8`)
+  74  an IO error occurred reading the program, its input, or writing output
+";
+
+// Exit codes, from `sysexits.h`.
+const EX_USAGE: u8 = 64;
+const EX_DATAERR: u8 = 65;
+const EX_IOERR: u8 = 74;
+
+/// How many parse errors to print before giving up.
+const MAX_REPORTED_ERRORS: usize = 20;
+
+enum Program {
+    File(PathBuf),
+    Source(String),
+    Stdin,
+}
+
+enum ProgramInput {
+    File(PathBuf),
+    Str(String),
+    Stdin,
+}
+
+struct Args {
+    program: Program,
+    input: ProgramInput,
+    strict: bool,
+    repl: bool,
+    record: Option<PathBuf>,
+    replay: Option<PathBuf>,
+    eval: EvalOptions,
+}
+
+/// Returns `Ok(None)` if `--help` was requested.
+fn parse_args(mut args: impl Iterator<Item = OsString>) -> Result<Option<Args>, String> {
+    let mut program = None;
+    let mut input = ProgramInput::Stdin;
+    let mut strict = false;
+    let mut repl = false;
+    let mut record = None;
+    let mut replay = None;
+    let mut eval = EvalOptions::default();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| format!("missing value for `{}`", name))
+        };
+        let utf8 = |v: OsString| {
+            v.into_string()
+                .map_err(|v| format!("argument is not valid UTF-8: {:?}", v))
+        };
+        match arg.to_str() {
+            Some("-h") | Some("--help") => return Ok(None),
+            Some("--strict") => strict = true,
+            Some("-r") | Some("--repl") => repl = true,
+            Some("--latin1") => eval.encoding = Encoding::Latin1,
+            Some(f @ "--max-steps") => {
+                let n = utf8(value(f)?)?;
+                let n = n.parse().map_err(|_| format!("bad step count `{}`", n))?;
+                eval.max_steps = Some(n);
+            }
+            Some(f @ "--max-objects") => {
+                let n = utf8(value(f)?)?;
+                let n = n.parse().map_err(|_| format!("bad object count `{}`", n))?;
+                eval.max_objects = Some(n);
+            }
+            Some(f @ "--max-output") => {
+                let n = utf8(value(f)?)?;
+                let n = n.parse().map_err(|_| format!("bad output limit `{}`", n))?;
+                eval.max_output = Some(n);
+            }
+            Some(f @ "--timeout") => {
+                let secs = utf8(value(f)?)?;
+                let t = secs
+                    .parse()
+                    .ok()
+                    .and_then(|s| std::time::Duration::try_from_secs_f64(s).ok())
+                    .ok_or_else(|| format!("bad timeout `{}`", secs))?;
+                eval.timeout = Some(t);
+            }
+            Some(f @ "--invalid-utf8") => {
+                eval.invalid_utf8 = match utf8(value(f)?)?.as_str() {
+                    "replace" => Utf8Policy::Replace,
+                    "skip" => Utf8Policy::Skip,
+                    "error" => Utf8Policy::Error,
+                    other => return Err(format!("bad value for `{}`: `{}`", f, other)),
+                };
+            }
+            Some(f @ "--record") => record = Some(value(f)?.into()),
+            Some(f @ "--replay") => replay = Some(value(f)?.into()),
+            Some(f @ "-i") | Some(f @ "--input") => {
+                input = ProgramInput::File(value(f)?.into());
+            }
+            Some(f @ "-s") | Some(f @ "--input-str") => {
+                input = ProgramInput::Str(utf8(value(f)?)?);
+            }
+            Some(f @ "-e") | Some(f @ "--eval") => {
+                let src = Program::Source(utf8(value(f)?)?);
+                if program.replace(src).is_some() {
+                    return Err("more than one program provided".into());
+                }
+            }
+            Some(f) if f.starts_with('-') && f != "-" => {
+                return Err(format!("unknown option `{}`", f));
+            }
+            _ => {
+                let file = if arg == "-" {
+                    Program::Stdin
+                } else {
+                    Program::File(arg.into())
+                };
+                if program.replace(file).is_some() {
+                    return Err("more than one program provided".into());
+                }
+            }
+        }
+    }
+    if repl && program.is_some() {
+        return Err("`--repl` can't be used with a program".into());
+    }
+    if repl && (record.is_some() || replay.is_some()) {
+        return Err("`--repl` can't be used with `--record` or `--replay`".into());
+    }
+    Ok(Some(Args {
+        program: program.unwrap_or(Program::Stdin),
+        input,
+        strict,
+        repl,
+        record,
+        replay,
+        eval,
+    }))
+}
+
+fn program_input(args: &Args) -> Input<'_> {
+    match &args.input {
+        ProgramInput::File(p) => Input::File(p),
+        ProgramInput::Str(s) => Input::Str(s),
+        ProgramInput::Stdin => Input::Stdin,
+    }
+}
+
+/// The program's input in the REPL, if none was given: stdin is where the
+/// expressions come from, so reading from it would eat the user's next lines.
+struct NoInput;
+
+impl Read for NoInput {
+    fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+        Err(std::io::Error::other(
+            "no input in the REPL (provide it with `--input` or `--input-str`)",
+        ))
+    }
+}
+
+fn repl(args: &Args) -> Result<(), EvalError> {
+    let input: Box<dyn Read + Send> = match args.input {
+        ProgramInput::Stdin => Box::new(NoInput),
+        _ => program_input(args).reader()?,
+    };
+    let ctx = Ctx::with_options(input, std::io::stdout(), args.eval.clone());
+    let mut repl = Repl::new(ctx);
+    let stdin = std::io::stdin();
+    let mut line = String::new();
+    loop {
+        print!("{}", if repl.is_pending() { ". " } else { "> " });
+        std::io::stdout().flush()?;
+        line.clear();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            println!();
+            return Ok(());
+        }
+        let reply = repl.feed_line(&line);
+        std::io::stdout().flush()?;
+        match reply {
+            Ok(Reply::Value(v)) => println!("{}", v),
+            Ok(_) => {}
+            Err(e) => eprintln!("error: {}", e),
+        }
+    }
+}
+
+fn run(args: &Args) -> Result<(), EvalError> {
+    if args.repl {
+        return repl(args);
+    }
+    let mut opts = ParseOptions::default();
+    opts.strict = args.strict;
+    opts.log_warnings = !args.strict;
+    let (source, file) = match &args.program {
+        Program::File(p) => {
+            let source = std::fs::read(p)
+                .map_err(|e| std::io::Error::new(e.kind(), format!("{}: {}", p.display(), e)))?;
+            (source, Some(p))
+        }
+        Program::Source(s) => (s.clone().into_bytes(), None),
+        Program::Stdin => {
+            let mut source = vec![];
+            std::io::stdin().read_to_end(&mut source)?;
+            (source, None)
+        }
+    };
+    // This takes care of complaining if it isn't UTF-8.
+    let parsed = unlambda::parse_from_reader(&source[..], opts.clone());
+    let expr = match parsed {
+        Ok(expr) => expr,
+        Err(e) => {
+            let in_file = |e: ParseError| match file {
+                Some(p) => e.with_file(p),
+                None => e,
+            };
+            let e = in_file(e);
+            if e.offset().is_some() {
+                // Go back over it to report everything that's wrong at once.
+                let source = String::from_utf8_lossy(&source);
+                let errors = unlambda::parse_recovering(&source, opts).1;
+                let more = errors.len().saturating_sub(MAX_REPORTED_ERRORS);
+                for (i, err) in errors.into_iter().take(MAX_REPORTED_ERRORS).enumerate() {
+                    if i != 0 {
+                        eprintln!();
+                    }
+                    eprint!("{}", in_file(err).render(&source));
+                }
+                if more > 0 {
+                    eprintln!("... and {} more", more);
+                }
+            }
+            return Err(e.into());
+        }
+    };
+    let input = program_input(args).reader()?;
+    let mut ctx = Ctx::with_options(input, std::io::stdout(), args.eval.clone());
+    if let Some(path) = &args.replay {
+        let t = std::fs::read_to_string(path)?
+            .parse::<Transcript>()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        ctx.replay(t);
+    } else if args.record.is_some() {
+        ctx.record();
+    }
+    let res = ctx.execute(expr).and_then(|()| ctx.finish_replay());
+    std::io::stdout().flush()?;
+    if let (Some(path), Some(t)) = (&args.record, ctx.take_transcript()) {
+        std::fs::write(path, t.to_string())?;
+    }
+    res
+}
+
+fn exit_code(e: &EvalError) -> u8 {
+    match e {
+        // The program's source not being UTF-8 is its problem, not the IO's.
+        EvalError::Parse(pe) => match pe.kind() {
+            ParseErrorKind::Io(e) if e.kind() != std::io::ErrorKind::InvalidData => EX_IOERR,
+            _ => EX_DATAERR,
+        },
+        EvalError::Io(_) => EX_IOERR,
+        EvalError::InvalidUtf8(_) => EX_DATAERR,
+        _ => 1,
+    }
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args(std::env::args_os().skip(1)) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            print!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(msg) => {
+            eprintln!("unlambda: {}\n\n{}", msg, USAGE);
+            return ExitCode::from(EX_USAGE);
+        }
+    };
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            // Parse errors with a position were already shown with the
+            // source.
+            if !matches!(&e, EvalError::Parse(pe) if pe.offset().is_some()) {
+                eprintln!("unlambda: {}", e);
+            }
+            ExitCode::from(exit_code(&e))
+        }
+    }
+}
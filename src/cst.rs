@@ -0,0 +1,305 @@
+//! A lossless syntax tree. An [`Expr`] only knows what the program means, but
+//! a [`SyntaxTree`] also keeps the comments and whitespace (the "trivia")
+//! around each token, so it writes back out as exactly the source it came
+//! from. Useful for formatters and such, which need to rewrite a program
+//! without destroying its documentation.
+//!
+//! The tree is stored as a flat list of tokens (unlambda is prefix notation,
+//! so that's enough to know its shape), which means deeply nested programs
+//! are no problem. [`Node`] gives a tree-shaped view of it.
+
+use super::*;
+use lex::TokenKind;
+
+/// Parse `s` into a [`SyntaxTree`]. Errors are the same as for
+/// [`parse_from_str`]. Without [`ParseOptions::strict`], anything after the
+/// program ends up in [`SyntaxTree::trailing`] as [`Trivia::Skipped`].
+pub fn parse_lossless(s: &str, o: ParseOptions) -> Result<SyntaxTree, ParseError> {
+    let tokens = parse_tokens(s, o)?;
+    let end = tokens.iter().map(Token::len).sum::<usize>();
+    let mut trailing: Vec<Trivia> = vec![];
+    for t in lex::tokenize(&s[end..]) {
+        let text = &s[end..][t.range];
+        match (Trivia::from_lexeme(t.kind, text), trailing.last_mut()) {
+            (Some(trivia), _) => trailing.push(trivia),
+            (None, Some(Trivia::Skipped(prev))) => prev.push_str(text),
+            (None, _) => trailing.push(Trivia::Skipped(text.into())),
+        }
+    }
+    Ok(SyntaxTree::new(tokens, trailing))
+}
+
+/// Text that doesn't affect the meaning of the program.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Trivia {
+    Whitespace(String),
+    /// Including the `#`, but not the newline that ends it.
+    Comment(String),
+    /// Anything after the end of the program, which is ignored if we aren't
+    /// being strict.
+    Skipped(String),
+}
+
+impl Trivia {
+    pub fn text(&self) -> &str {
+        match self {
+            Self::Whitespace(s) | Self::Comment(s) | Self::Skipped(s) => s,
+        }
+    }
+
+    fn from_lexeme(kind: TokenKind, text: &str) -> Option<Self> {
+        match kind {
+            TokenKind::Whitespace => Some(Self::Whitespace(text.into())),
+            TokenKind::Comment => Some(Self::Comment(text.into())),
+            _ => None,
+        }
+    }
+}
+
+/// Trivia that was refused by [`SyntaxTree::set_leading`] or
+/// [`SyntaxTree::set_trailing`], since it would change what the program
+/// means (or isn't what it says it is).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidTrivia(pub Trivia);
+
+impl std::fmt::Display for InvalidTrivia {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid trivia: {:?}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidTrivia {}
+
+/// Check that `trivia` reads back as itself, and doesn't swallow or add
+/// to what comes after it. If it's the tree's trailing trivia, nothing comes
+/// after it, and skipped text is allowed.
+fn check_trivia(trivia: &[Trivia], trailing: bool) -> Result<(), InvalidTrivia> {
+    for (i, t) in trivia.iter().enumerate() {
+        let ok = match t {
+            Trivia::Whitespace(s) => !s.is_empty() && s.chars().all(char::is_whitespace),
+            // The newline that ends it has to come next, or it'd run into
+            // whatever does.
+            Trivia::Comment(s) => {
+                s.starts_with('#')
+                    && !s.contains('\n')
+                    && match trivia.get(i + 1) {
+                        Some(Trivia::Whitespace(ws)) => ws.starts_with('\n'),
+                        Some(_) => false,
+                        None => trailing,
+                    }
+            }
+            Trivia::Skipped(_) => trailing,
+        };
+        if !ok {
+            return Err(InvalidTrivia(t.clone()));
+        }
+    }
+    Ok(())
+}
+
+/// A backtick or a builtin, along with the trivia before it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Token {
+    leading: Vec<Trivia>,
+    text: String,
+    func: Option<Func>,
+}
+
+impl Token {
+    /// The token `text`, after the trivia in `trivia`.
+    pub(crate) fn new(trivia: &str, text: &str, func: Option<Func>) -> Self {
+        let leading = lex::tokenize(trivia)
+            .filter_map(|t| Trivia::from_lexeme(t.kind, &trivia[t.range]))
+            .collect();
+        Self {
+            leading,
+            text: text.into(),
+            func,
+        }
+    }
+
+    /// The comments and whitespace before the token.
+    pub fn leading(&self) -> &[Trivia] {
+        &self.leading
+    }
+
+    /// The token as written, e.g. `` ` ``, `K`, or `.x`.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// The builtin, or `None` for a backtick.
+    pub fn func(&self) -> Option<&Func> {
+        self.func.as_ref()
+    }
+
+    pub fn is_backtick(&self) -> bool {
+        self.func.is_none()
+    }
+
+    /// The length of the source this came from.
+    fn len(&self) -> usize {
+        self.leading.iter().map(|t| t.text().len()).sum::<usize>() + self.text.len()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SyntaxTree {
+    /// In prefix order, i.e. in the order they're written.
+    tokens: Vec<Token>,
+    /// For each token, the index just past the last token of the expression
+    /// that starts there.
+    ends: Vec<usize>,
+    /// Trivia after the last token.
+    trailing: Vec<Trivia>,
+}
+
+impl SyntaxTree {
+    fn new(tokens: Vec<Token>, trailing: Vec<Trivia>) -> Self {
+        let mut ends = vec![0; tokens.len()];
+        for i in (0..tokens.len()).rev() {
+            ends[i] = if tokens[i].is_backtick() {
+                // The operator starts right after us, the operand right
+                // after that.
+                ends[ends[i + 1]]
+            } else {
+                i + 1
+            };
+        }
+        Self {
+            tokens,
+            ends,
+            trailing,
+        }
+    }
+
+    pub fn root(&self) -> Node<'_> {
+        Node {
+            tree: self,
+            index: 0,
+        }
+    }
+
+    pub fn tokens(&self) -> &[Token] {
+        &self.tokens
+    }
+
+    /// Trivia after the last token.
+    pub fn trailing(&self) -> &[Trivia] {
+        &self.trailing
+    }
+
+    /// Replace the trivia before the token at `index`. Comments have to be
+    /// followed by whitespace starting with a newline, and
+    /// [`Trivia::Skipped`] isn't allowed, since the program's not over.
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn set_leading(&mut self, index: usize, trivia: Vec<Trivia>) -> Result<(), InvalidTrivia> {
+        check_trivia(&trivia, false)?;
+        self.tokens[index].leading = trivia;
+        Ok(())
+    }
+
+    /// Replace the trivia after the last token. Like
+    /// [`set_leading`](Self::set_leading), except that this can end with a
+    /// comment, and have [`Trivia::Skipped`] in it.
+    pub fn set_trailing(&mut self, trivia: Vec<Trivia>) -> Result<(), InvalidTrivia> {
+        check_trivia(&trivia, true)?;
+        self.trailing = trivia;
+        Ok(())
+    }
+
+    /// The expression this is the syntax of.
+    pub fn to_expr(&self) -> P<Expr> {
+        self.root().to_expr()
+    }
+}
+
+/// Builds a tree without any trivia. Builtins are written the same way as in
+/// `Expr`'s `Display` impl.
+impl From<&Expr> for SyntaxTree {
+    fn from(e: &Expr) -> Self {
+        let mut tokens = vec![];
+        let mut stack = vec![e];
+        while let Some(e) = stack.pop() {
+            let func = match e {
+                Expr::App(operator, operand) => {
+                    stack.push(operand);
+                    stack.push(operator);
+                    None
+                }
+                Expr::Func(f) => Some(f.clone()),
+            };
+            let text = func.as_ref().map_or("`".into(), ToString::to_string);
+            tokens.push(Token::new("", &text, func));
+        }
+        Self::new(tokens, vec![])
+    }
+}
+
+impl std::fmt::Display for SyntaxTree {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for t in &self.tokens {
+            for trivia in &t.leading {
+                f.write_str(trivia.text())?;
+            }
+            f.write_str(&t.text)?;
+        }
+        for trivia in &self.trailing {
+            f.write_str(trivia.text())?;
+        }
+        Ok(())
+    }
+}
+
+/// An expression in a [`SyntaxTree`]: either an application, starting with a
+/// backtick, or a single builtin.
+#[derive(Clone, Copy, Debug)]
+pub struct Node<'a> {
+    tree: &'a SyntaxTree,
+    index: usize,
+}
+
+impl<'a> Node<'a> {
+    /// The backtick, for an application, otherwise the builtin.
+    pub fn token(&self) -> &'a Token {
+        &self.tree.tokens[self.index]
+    }
+
+    /// All the tokens of this expression.
+    pub fn tokens(&self) -> &'a [Token] {
+        &self.tree.tokens[self.index..self.tree.ends[self.index]]
+    }
+
+    /// The operator and operand, if this is an application.
+    pub fn children(&self) -> Option<(Node<'a>, Node<'a>)> {
+        if !self.token().is_backtick() {
+            return None;
+        }
+        let operator = self.index + 1;
+        let operand = self.tree.ends[operator];
+        let node = |index| Node {
+            tree: self.tree,
+            index,
+        };
+        Some((node(operator), node(operand)))
+    }
+
+    pub fn to_expr(&self) -> P<Expr> {
+        // Going backwards, each backtick applies the two expressions we
+        // just built.
+        let mut stack = vec![];
+        for t in self.tokens().iter().rev() {
+            let e = match &t.func {
+                Some(f) => Expr::Func(f.clone()),
+                None => {
+                    let operator = stack.pop().expect("operator");
+                    let operand = stack.pop().expect("operand");
+                    Expr::App(operator, operand)
+                }
+            };
+            stack.push(p(e));
+        }
+        stack.pop().expect("empty syntax tree")
+    }
+}
@@ -1,38 +1,203 @@
 //! Evaluation API. By far ths most useful part of this crate.
 use super::*;
-use std::{fs::File, io::Cursor};
-fn eval_imp(e: P<Expr>, input: Input<'_>, o: Output) -> Result<Output, Error> {
-    let mut ctx = match input {
-        Input::Str(s) => Ctx::new(Cursor::new(s.to_owned()), o),
-        Input::File(s) => Ctx::new(File::open(s)?, o),
-        Input::Stdin => Ctx::new(std::io::stdin(), o),
-    };
-    ctx.execute(e)?;
-    Ok(ctx.stdout)
-}
-
-pub fn eval_to_vec<'a, I: 'a + Into<Input<'a>>>(
+use std::{
+    io::Write,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// Options for evaluation. Use with [`Ctx::with_options`], or the `_with`
+/// variants of the `eval_to_*` functions, e.g. [`eval_to_string_with`].
+///
+/// The defaults put no limits on the program at all.
+#[derive(Default, Clone, Debug)]
+#[non_exhaustive]
+pub struct EvalOptions {
+    /// Stop with [`Error::StepLimit`] after this many steps of the interpreter
+    /// loop. Useful for programs you don't trust to terminate, e.g.
+    /// ```` ```sii``sii ````.
+    pub max_steps: Option<u64>,
+    /// Stop with [`Error::ObjectLimit`] if the program ever has more than this
+    /// many interpreter objects (expressions, continuations and closures) alive
+    /// at once. This catches programs that eat memory without taking many
+    /// steps to do it.
+    pub max_objects: Option<usize>,
+    /// Stop with [`Error::OutputLimit`] if the program tries to print more than
+    /// this many characters.
+    pub max_output: Option<u64>,
+    /// Stop with [`Error::TimedOut`] once evaluation has been running for this
+    /// long.
+    pub timeout: Option<Duration>,
+    /// Stop with [`Error::Cancelled`] once this is cancelled.
+    pub cancel: Option<CancelHandle>,
+    /// How the program's input and output are encoded. Defaults to UTF-8.
+    pub encoding: io::Encoding,
+    /// What to do if the program reads input that isn't valid UTF-8. By
+    /// default it's replaced with U+FFFD.
+    pub invalid_utf8: io::Utf8Policy,
+    /// Whether the program may do IO. With [`IoPolicy::Virtual`], the
+    /// program's output doesn't go where it usually would (so e.g.
+    /// [`eval_to_string_with`] returns an empty string): use a [`Ctx`] and
+    /// look at [`Ctx::device`] instead.
+    ///
+    /// [`IoPolicy::Virtual`]: io::IoPolicy::Virtual
+    pub io: io::IoPolicy,
+}
+
+/// A flag that can be used to stop evaluation from somewhere else, e.g.
+/// another thread. Clones share the same flag.
+///
+/// Note that this and [`EvalOptions::timeout`] are only checked every so often
+/// (every 1024 steps at the moment), and can't interrupt a program blocked
+/// reading input.
+#[derive(Clone, Debug, Default)]
+pub struct CancelHandle(Arc<AtomicBool>);
+
+impl CancelHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ask evaluations using this handle to stop.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+fn eval_imp<'i>(
+    e: P<Expr>,
+    input: impl ProgramInput<'i>,
+    o: Output<'i>,
+    opts: EvalOptions,
+) -> Result<(Func, Output<'i>), Error> {
+    let mut ctx = Ctx::with_options(input.into_reader()?, o, opts);
+    match ctx.evaluate(e) {
+        Ok(value) => Ok((value, ctx.stdout)),
+        Err(Error::OutputLimit(_)) => match ctx.stdout {
+            Output::Buffer(b) => Err(Error::OutputLimit(b)),
+            _ => Err(Error::OutputLimit(vec![])),
+        },
+        Err(e) => Err(e),
+    }
+}
+
+pub fn eval_to_vec<'a, 'i, I: 'a + Into<Input<'a>>>(
     source: I,
-    input: Input<'_>,
+    input: impl ProgramInput<'i>,
 ) -> Result<Vec<u8>, Error> {
-    match eval_imp(source.into().parse()?, input, Vec::with_capacity(32).into())? {
-        Output::Buffer(b) => Ok(b),
-        _ => unreachable!(),
-    }
+    eval_to_vec_with(source, input, EvalOptions::default())
 }
 
-pub fn eval_to_stdout<'a, I: 'a + Into<Input<'a>>>(
+pub fn eval_to_stdout<'a, 'i, I: 'a + Into<Input<'a>>>(
     source: I,
-    input: Input<'_>,
+    input: impl ProgramInput<'i>,
 ) -> Result<(), Error> {
-    eval_imp(source.into().parse()?, input, std::io::stdout().into()).map(drop)
+    eval_to_stdout_with(source, input, EvalOptions::default())
+}
+
+pub fn eval_to_string<'a, 'i, I: 'a + Into<Input<'a>>>(
+    source: I,
+    input: impl ProgramInput<'i>,
+) -> Result<String, Error> {
+    eval_to_string_with(source, input, EvalOptions::default())
+}
+
+pub fn eval_to_vec_with<'a, 'i, I: 'a + Into<Input<'a>>>(
+    source: I,
+    input: impl ProgramInput<'i>,
+    opts: EvalOptions,
+) -> Result<Vec<u8>, Error> {
+    eval_value_with(source, input, opts).map(|(_, out)| out)
+}
+
+pub fn eval_to_stdout_with<'a, 'i, I: 'a + Into<Input<'a>>>(
+    source: I,
+    input: impl ProgramInput<'i>,
+    opts: EvalOptions,
+) -> Result<(), Error> {
+    let expr = source.into().parse_for(&opts)?;
+    eval_imp(expr, input, std::io::stdout().into(), opts).map(drop)
+}
+
+/// Evaluate `source`, writing its output to `out` as it goes. `out` can be
+/// borrowed, e.g. a `&mut Vec<u8>`, and doesn't need to be `Send`, e.g. a
+/// [`StdoutLock`](std::io::StdoutLock), since it never leaves this call.
+pub fn eval_to_writer<'a, 'i, I: 'a + Into<Input<'a>>>(
+    source: I,
+    input: impl ProgramInput<'i>,
+    out: impl Write,
+) -> Result<(), Error> {
+    eval_to_writer_with(source, input, out, EvalOptions::default())
+}
+
+pub fn eval_to_writer_with<'a, 'i, I: 'a + Into<Input<'a>>>(
+    source: I,
+    input: impl ProgramInput<'i>,
+    mut out: impl Write,
+    opts: EvalOptions,
+) -> Result<(), Error> {
+    let expr = source.into().parse_for(&opts)?;
+    // The ctx has to be `Send` and `out` might not be, so the program prints
+    // to a buffer, which is passed on to `out` after every step.
+    let mut ctx = Ctx::with_options(input.into_reader()?, Vec::new(), opts);
+    let res = ctx.evaluate_with(expr, |ctx| ctx.drain_output(&mut out));
+    ctx.drain_output(&mut out)?;
+    match res {
+        Ok(_) => Ok(()),
+        // It's all been written already.
+        Err(Error::OutputLimit(_)) => Err(Error::OutputLimit(vec![])),
+        Err(e) => Err(e),
+    }
+}
+
+/// Evaluate `source` lazily, returning an iterator over the characters it
+/// prints. The program only runs when the iterator is advanced, so this works
+/// for programs that never finish, e.g. to look at the first few characters
+/// with `take(n)`.
+pub fn eval_chars<'a, 'i, I: 'a + Into<Input<'a>>>(
+    source: I,
+    input: impl ProgramInput<'i>,
+    opts: EvalOptions,
+) -> Result<machine::OutputChars<'i>, Error> {
+    let expr = source.into().parse_for(&opts)?;
+    Ok(machine::OutputChars::new(input.into_reader()?, expr, opts))
+}
+
+/// Evaluate `source`, returning the value it produced (see [`Ctx::evaluate`])
+/// along with its output.
+pub fn eval_value<'a, 'i, I: 'a + Into<Input<'a>>>(
+    source: I,
+    input: impl ProgramInput<'i>,
+) -> Result<(Func, Vec<u8>), Error> {
+    eval_value_with(source, input, EvalOptions::default())
+}
+
+pub fn eval_value_with<'a, 'i, I: 'a + Into<Input<'a>>>(
+    source: I,
+    input: impl ProgramInput<'i>,
+    opts: EvalOptions,
+) -> Result<(Func, Vec<u8>), Error> {
+    let out = Vec::with_capacity(32).into();
+    let expr = source.into().parse_for(&opts)?;
+    match eval_imp(expr, input, out, opts)? {
+        (value, Output::Buffer(b)) => Ok((value, b)),
+        _ => unreachable!(),
+    }
 }
 
-pub fn eval_to_string<'a, I: 'a + Into<Input<'a>>>(
+pub fn eval_to_string_with<'a, 'i, I: 'a + Into<Input<'a>>>(
     source: I,
-    input: Input<'_>,
+    input: impl ProgramInput<'i>,
+    opts: EvalOptions,
 ) -> Result<String, Error> {
-    match String::from_utf8(eval_to_vec(source, input)?) {
+    match String::from_utf8(eval_to_vec_with(source, input, opts)?) {
         Ok(s) => Ok(s),
         Err(e) => Ok(String::from_utf8_lossy(e.as_bytes()).to_string()),
     }
@@ -44,6 +209,35 @@ pub fn eval_to_string<'a, I: 'a + Into<Input<'a>>>(
 pub enum Error {
     Io(std::io::Error),
     Parse(ParseError),
+    /// The program ran for longer than [`EvalOptions::max_steps`]. Contains
+    /// the number of steps it took.
+    StepLimit(u64),
+    /// The program had more than [`EvalOptions::max_objects`] objects alive.
+    /// Contains the number it reached.
+    ObjectLimit(usize),
+    /// The program ran for longer than [`EvalOptions::timeout`].
+    TimedOut,
+    /// The program was stopped using [`EvalOptions::cancel`].
+    Cancelled,
+    /// The program tried to print more than [`EvalOptions::max_output`]
+    /// characters. Contains what it printed up to that point, if it was
+    /// printing to a buffer (e.g. with [`eval_to_string_with`]). Otherwise,
+    /// it's empty, and what got printed is wherever it was printed to.
+    OutputLimit(Vec<u8>),
+    /// The program's input wasn't valid UTF-8, and
+    /// [`EvalOptions::invalid_utf8`] was [`Utf8Policy::Error`]. Contains the
+    /// offset of the first bad byte in the input.
+    ///
+    /// [`Utf8Policy::Error`]: io::Utf8Policy::Error
+    InvalidUtf8(u64),
+    /// A program being replayed (see [`Ctx::replay`]) did something other
+    /// than what its transcript says it should have.
+    Diverged(transcript::Divergence),
+    /// The program tried to do IO, but [`EvalOptions::io`] was
+    /// [`IoPolicy::Deny`].
+    ///
+    /// [`IoPolicy::Deny`]: io::IoPolicy::Deny
+    IoNotPermitted(io::IoNotPermitted),
 }
 
 impl std::fmt::Display for Error {
@@ -51,6 +245,14 @@ impl std::fmt::Display for Error {
         match self {
             Self::Io(io) => io.fmt(f),
             Self::Parse(pe) => pe.fmt(f),
+            Self::StepLimit(n) => write!(f, "step limit exceeded after {} steps", n),
+            Self::ObjectLimit(n) => write!(f, "object limit exceeded with {} live objects", n),
+            Self::TimedOut => f.write_str("evaluation timed out"),
+            Self::Cancelled => f.write_str("evaluation cancelled"),
+            Self::OutputLimit(_) => f.write_str("output limit exceeded"),
+            Self::InvalidUtf8(at) => write!(f, "invalid UTF-8 in input at byte {}", at),
+            Self::Diverged(d) => d.fmt(f),
+            Self::IoNotPermitted(e) => e.fmt(f),
         }
     }
 }
@@ -61,6 +263,7 @@ impl std::error::Error for Error {
         match self {
             Self::Io(e) => Some(e),
             Self::Parse(pe) => Some(pe),
+            _ => None,
         }
     }
 }
@@ -0,0 +1,246 @@
+//! Evaluation as a [`Future`], for running programs in an async program
+//! without tying up a thread per program.
+//!
+//! This doesn't depend on any particular runtime. Input comes from an
+//! [`AsyncInput`] and output goes to an [`AsyncOutput`], which have the same
+//! signatures as the `AsyncRead`/`AsyncWrite` traits from `futures` or
+//! `tokio`, so adapting those only takes a few lines.
+
+use super::*;
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// How many steps [`Evaluation`] takes before giving other tasks a chance to
+/// run.
+const YIELD_INTERVAL: u64 = 1024;
+
+/// A source of input that might not be ready yet.
+pub trait AsyncInput {
+    /// Read some bytes into `buf`, returning how many were read. `Ok(0)` means
+    /// EOF.
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>>;
+}
+
+/// Somewhere to write output that might not be ready yet.
+pub trait AsyncOutput {
+    /// Write some of `buf`, returning how much was written.
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>>;
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>>;
+}
+
+impl AsyncInput for &[u8] {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(io::Read::read(self.get_mut(), buf))
+    }
+}
+
+impl<T: AsyncInput + Unpin + ?Sized> AsyncInput for &mut T {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut **self.get_mut()).poll_read(cx, buf)
+    }
+}
+
+impl<T: AsyncInput + Unpin + ?Sized> AsyncInput for Box<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut **self.get_mut()).poll_read(cx, buf)
+    }
+}
+
+impl AsyncOutput for Vec<u8> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T: AsyncOutput + Unpin + ?Sized> AsyncOutput for &mut T {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut **self.get_mut()).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut **self.get_mut()).poll_flush(cx)
+    }
+}
+
+impl<T: AsyncOutput + Unpin + ?Sized> AsyncOutput for Box<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut **self.get_mut()).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut **self.get_mut()).poll_flush(cx)
+    }
+}
+
+/// Evaluate `expr`, reading input from `input` and writing output to `output`.
+/// The returned future resolves to the value the program finished with, like
+/// [`Ctx::evaluate`].
+pub fn eval_async<R, W>(expr: P<Expr>, input: R, output: W, opts: EvalOptions) -> Evaluation<R, W>
+where
+    R: AsyncInput + Unpin,
+    W: AsyncOutput + Unpin,
+{
+    let mut ctx = Ctx::with_options(io::empty(), Vec::new(), opts);
+    ctx.begin();
+    Evaluation {
+        ctx,
+        task: Task::Eval(expr, p(Cont::Final)),
+        input,
+        output,
+        written: 0,
+    }
+}
+
+/// A program being evaluated asynchronously. See [`eval_async`].
+///
+/// Between steps, output is written to the [`AsyncOutput`] before the program
+/// continues, and before a step that reads input (`@`), the next character is
+/// read from the [`AsyncInput`], so the future only waits when the program
+/// does. It also yields every so often while computing, so a long-running
+/// program doesn't hog the executor.
+///
+/// All the limits in the [`EvalOptions`] apply, but note that
+/// [`EvalOptions::timeout`] counts time spent waiting for IO too.
+pub struct Evaluation<R, W> {
+    /// The program's output goes into this ctx's buffer, and its input comes
+    /// from its lookahead, which we fill.
+    ctx: Ctx<'static>,
+    task: Task,
+    input: R,
+    output: W,
+    /// How much of the output buffer has been written out.
+    written: usize,
+}
+
+impl<R, W> Evaluation<R, W>
+where
+    R: AsyncInput + Unpin,
+    W: AsyncOutput + Unpin,
+{
+    /// Write out everything buffered so far.
+    fn poll_write_output(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let buf = match &mut self.ctx.stdout {
+            Output::Buffer(b) => b,
+            _ => unreachable!("we always use a buffer"),
+        };
+        while self.written < buf.len() {
+            let n = match Pin::new(&mut self.output).poll_write(cx, &buf[self.written..]) {
+                Poll::Ready(Ok(0)) => return Poll::Ready(Err(io::ErrorKind::WriteZero.into())),
+                Poll::Ready(Ok(n)) => n,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            };
+            self.written += n;
+        }
+        buf.clear();
+        self.written = 0;
+        Poll::Ready(Ok(()))
+    }
+
+    /// Read until the ctx has the next character of input decoded.
+    fn poll_fill_input(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        while !self.ctx.input_ready()? {
+            let mut b = 0u8;
+            let n = match Pin::new(&mut self.input).poll_read(cx, std::slice::from_mut(&mut b)) {
+                Poll::Ready(Ok(n)) => n,
+                Poll::Ready(Err(e)) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e.into())),
+                Poll::Pending => return Poll::Pending,
+            };
+            self.ctx.lookahead.push(if n == 0 { None } else { Some(b) });
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_imp(&mut self, cx: &mut Context<'_>) -> Poll<Result<Func, Error>> {
+        let _tracking = self.ctx.track();
+        for _ in 0..YIELD_INTERVAL {
+            match self.poll_write_output(cx) {
+                Poll::Ready(Ok(())) => {}
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e.into())),
+                Poll::Pending => return Poll::Pending,
+            }
+            if let Task::Final(v) = &self.task {
+                return match Pin::new(&mut self.output).poll_flush(cx) {
+                    Poll::Ready(Ok(())) => Poll::Ready(Ok(v.clone())),
+                    Poll::Ready(Err(e)) => Poll::Ready(Err(e.into())),
+                    Poll::Pending => Poll::Pending,
+                };
+            }
+            if self.ctx.needs_input(&self.task) {
+                match self.poll_fill_input(cx) {
+                    Poll::Ready(Ok(())) => {}
+                    Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+            self.ctx.tick()?;
+            // As in `Machine`, a failed step gives the task back, so we don't
+            // copy it first.
+            let task = std::mem::replace(&mut self.task, Task::Final(Func::V));
+            match task.try_step(&mut self.ctx) {
+                Ok(task) => self.task = task,
+                Err((e, task)) => {
+                    self.task = task;
+                    return Poll::Ready(Err(e));
+                }
+            }
+        }
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+impl<R, W> Future for Evaluation<R, W>
+where
+    R: AsyncInput + Unpin,
+    W: AsyncOutput + Unpin,
+{
+    type Output = Result<Func, Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.get_mut().poll_imp(cx)
+    }
+}
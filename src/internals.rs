@@ -2,7 +2,9 @@
 
 use super::*;
 
-#[derive(Debug, Clone, PartialEq)]
+/// Equality ignores [`OpFunc::Located`], which is the same as the builtin it
+/// wraps.
+#[derive(Debug, Clone)]
 pub enum Func {
     V,
     I,
@@ -35,6 +37,34 @@ impl Func {
     pub fn d1(v: P<Expr>) -> Self {
         Self::Op(Box::new(OpFunc::D1(v)))
     }
+    pub(crate) fn located(f: Func, span: Span) -> Self {
+        Self::Op(Box::new(OpFunc::Located(f, span)))
+    }
+
+    /// The builtin without where it came from, if it's [`OpFunc::Located`].
+    pub(crate) fn unlocated(self) -> Self {
+        match self {
+            Self::Op(mut o) => {
+                if let OpFunc::Located(f, _) = &mut *o {
+                    std::mem::replace(f, Func::V)
+                } else {
+                    Self::Op(o)
+                }
+            }
+            f => f,
+        }
+    }
+}
+
+impl PartialEq for Func {
+    fn eq(&self, other: &Self) -> bool {
+        match (self.location().0, other.location().0) {
+            (Self::Dot(a), Self::Dot(b)) | (Self::Q(a), Self::Q(b)) => a == b,
+            (Self::Op(a), Self::Op(b)) => a == b,
+            (Self::Dot(_) | Self::Q(_) | Self::Op(_), _) => false,
+            (a, b) => std::mem::discriminant(a) == std::mem::discriminant(b),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -44,29 +74,68 @@ pub enum OpFunc {
     S2(Func, Func),
     Cont(P<Cont>),
     D1(P<Expr>),
+    /// An IO builtin that knows where in the source it came from, so that
+    /// [`Error::IoNotPermitted`] can say. Otherwise it's the same as the
+    /// builtin. See [`ParseOptions::locate_io`].
+    Located(Func, Span),
 }
 
 impl Func {
-    pub fn apply_to(self, ctx: &mut Ctx, operand: Func, cont: P<Cont>) -> Result<Task, Error> {
+    /// True for the builtins that do IO (or look at the current character):
+    /// `.x`, `r`, `@`, `?x` and `|`.
+    pub fn is_io(&self) -> bool {
+        match self {
+            Self::Dot(_) | Self::R | Self::At | Self::Q(_) | Self::Pipe => true,
+            Self::Op(o) => matches!(&**o, OpFunc::Located(f, _) if f.is_io()),
+            _ => false,
+        }
+    }
+
+    /// The builtin, and where it came from if it's [`OpFunc::Located`].
+    pub(crate) fn location(&self) -> (&Func, Option<Span>) {
+        match self {
+            Self::Op(o) => match &**o {
+                OpFunc::Located(f, span) => (f, Some(*span)),
+                _ => (self, None),
+            },
+            _ => (self, None),
+        }
+    }
+
+    pub fn apply_to(self, ctx: &mut Ctx<'_>, operand: Func, cont: P<Cont>) -> Result<Task, Error> {
+        self.try_apply(ctx, operand, cont).map_err(|(e, _)| e)
+    }
+
+    /// Like [`Func::apply_to`], but on failure also gives back the task as it
+    /// was before, so it can be retried. Anything that can fail happens before
+    /// any of our arguments are used up.
+    pub(crate) fn try_apply(
+        self,
+        ctx: &mut Ctx<'_>,
+        operand: Func,
+        cont: P<Cont>,
+    ) -> Result<Task, (Error, Task)> {
+        if let Err(e) = ctx.check_io(&self) {
+            return Err((e, Task::App(self, operand, cont)));
+        }
         Ok(match self {
-            Self::V => cont.invoke(self),
-            Self::I => cont.invoke(operand),
-            Self::E => Task::Final,
+            Self::V => Cont::resume(cont, self),
+            Self::I => Cont::resume(cont, operand),
+            Self::E => Task::Final(operand.unlocated()),
             Self::C => Task::App(operand, Func::cont(cont.clone()), cont),
-            Self::R => {
-                ctx.putc('\n')?;
-                cont.invoke(operand)
-            }
-            Self::D => cont.invoke(Func::d1(p(Expr::Func(operand)))),
+            Self::R => match ctx.putc('\n') {
+                Ok(()) => Cont::resume(cont, operand),
+                Err(e) => return Err((e, Task::App(self, operand, cont))),
+            },
+            Self::D => Cont::resume(cont, Func::d1(p(Expr::Func(operand)))),
 
-            Self::At => {
-                let f = if ctx.getc()?.is_some() {
-                    Func::I
-                } else {
-                    Func::V
-                };
-                Task::App(operand, f, cont)
-            }
+            Self::At => match ctx.getc() {
+                Ok(c) => {
+                    let f = if c.is_some() { Func::I } else { Func::V };
+                    Task::App(operand, f, cont)
+                }
+                Err(e) => return Err((e, Task::App(self, operand, cont))),
+            },
 
             Self::Pipe => {
                 let f = if let Some(c) = ctx.last_char() {
@@ -76,10 +145,10 @@ impl Func {
                 };
                 Task::App(f, operand, cont)
             }
-            Self::Dot(c) => {
-                ctx.putc(c)?;
-                cont.invoke(operand)
-            }
+            Self::Dot(c) => match ctx.putc(c) {
+                Ok(()) => Cont::resume(cont, operand),
+                Err(e) => return Err((e, Task::App(self, operand, cont))),
+            },
             Self::Q(ch) => {
                 let f = if ctx.last_char() == Some(ch) {
                     Func::I
@@ -88,31 +157,119 @@ impl Func {
                 };
                 Task::App(f, operand, cont)
             }
-            Self::S => cont.invoke(Func::s1(operand)),
-            Self::K => cont.invoke(Func::k1(operand)),
+            Self::S => Cont::resume(cont, Func::s1(operand)),
+            Self::K => Cont::resume(cont, Func::k1(operand)),
 
-            Self::Op(o) => match *o {
-                OpFunc::K1(v) => cont.invoke(v),
+            Self::Op(o) => {
+                // Cloning the fields would copy everything they hold, so move
+                // them out instead. (We can't just destructure it, since it has
+                // a `Drop` impl.)
+                let mut o = *o;
+                let take = |f: &mut Func| std::mem::replace(f, Func::V);
+                match &mut o {
+                    OpFunc::K1(v) => Cont::resume(cont, take(v)),
 
-                OpFunc::S1(x) => cont.invoke(Func::s2(x, operand)),
+                    OpFunc::S1(x) => Cont::resume(cont, Func::s2(take(x), operand)),
+                    OpFunc::S2(x, y) => {
+                        let operand = p(Expr::Func(operand));
+                        Task::Eval(
+                            p(Expr::App(
+                                p(Expr::App(p(Expr::Func(take(x))), operand.clone())),
+                                p(Expr::App(p(Expr::Func(take(y))), operand)),
+                            )),
+                            cont,
+                        )
+                    }
+                    OpFunc::Cont(c) => {
+                        let c = CONT_HOLE.with(|h| std::mem::replace(c, h.clone()));
+                        Cont::resume(c, operand)
+                    }
+                    OpFunc::D1(promise) => {
+                        let cont = Cont::Del(operand, cont);
+                        Task::Eval(promise.clone(), p(cont))
+                    }
+                    OpFunc::Located(f, span) => {
+                        let span = *span;
+                        return take(f)
+                            .try_apply(ctx, operand, cont)
+                            .map_err(|(e, t)| match t {
+                                Task::App(f, operand, cont) => {
+                                    (e, Task::App(Func::located(f, span), operand, cont))
+                                }
+                                t => (e, t),
+                            });
+                    }
+                }
+            }
+        })
+    }
+}
+
+/// Prints the value as unlambda source, where possible. Continuations have no
+/// source representation, and print as `<cont>`.
+impl std::fmt::Display for Func {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write_source(f, Source::Func(self))
+    }
+}
+
+/// Something still to be written by [`write_source`].
+enum Source<'a> {
+    Func(&'a Func),
+    Expr(&'a Expr),
+}
+
+/// Writes `start` as unlambda source. This keeps the parts it hasn't got to
+/// yet on an explicit stack, rather than recursing, so deep values are fine.
+fn write_source(f: &mut std::fmt::Formatter<'_>, start: Source<'_>) -> std::fmt::Result {
+    let mut stack = vec![start];
+    while let Some(next) = stack.pop() {
+        let func = match next {
+            Source::Expr(Expr::App(operator, operand)) => {
+                f.write_str("`")?;
+                stack.push(Source::Expr(operand));
+                stack.push(Source::Expr(operator));
+                continue;
+            }
+            Source::Expr(Expr::Func(func)) | Source::Func(func) => func,
+        };
+        match func {
+            Func::V => f.write_str("v")?,
+            Func::I => f.write_str("i")?,
+            Func::E => f.write_str("e")?,
+            Func::C => f.write_str("c")?,
+            Func::D => f.write_str("d")?,
+            Func::At => f.write_str("@")?,
+            Func::Pipe => f.write_str("|")?,
+            Func::R | Func::Dot('\n') => f.write_str("r")?,
+            Func::K => f.write_str("k")?,
+            Func::S => f.write_str("s")?,
+            Func::Dot(c) => write!(f, ".{}", c)?,
+            Func::Q(c) => write!(f, "?{}", c)?,
+            Func::Op(o) => match &**o {
+                OpFunc::K1(x) => {
+                    f.write_str("`k")?;
+                    stack.push(Source::Func(x));
+                }
+                OpFunc::S1(x) => {
+                    f.write_str("`s")?;
+                    stack.push(Source::Func(x));
+                }
                 OpFunc::S2(x, y) => {
-                    let operand = p(Expr::Func(operand));
-                    Task::Eval(
-                        p(Expr::App(
-                            p(Expr::App(p(Expr::Func(x)), operand.clone())),
-                            p(Expr::App(p(Expr::Func(y)), operand)),
-                        )),
-                        cont,
-                    )
+                    f.write_str("``s")?;
+                    stack.push(Source::Func(y));
+                    stack.push(Source::Func(x));
                 }
-                OpFunc::Cont(c) => c.invoke(operand),
-                OpFunc::D1(promise) => {
-                    let cont = Cont::Del(operand, cont);
-                    Task::Eval(promise, p(cont))
+                OpFunc::Cont(_) => f.write_str("<cont>")?,
+                OpFunc::D1(e) => {
+                    f.write_str("`d")?;
+                    stack.push(Source::Expr(e));
                 }
+                OpFunc::Located(x, _) => stack.push(Source::Func(x)),
             },
-        })
+        }
     }
+    Ok(())
 }
 
 #[derive(Clone, PartialEq, Debug)]
@@ -122,14 +279,31 @@ pub enum Expr {
 }
 
 impl Expr {
-    pub fn eval(self, cont: P<Cont>) -> Task {
-        match self {
-            Self::Func(f) => cont.invoke(f),
-            Self::App(operator, operand) => Task::Eval(operator, p(Cont::App1(operand, cont))),
+    pub fn eval(mut self, cont: P<Cont>) -> Task {
+        // We can't move out of `self`, since it has a `Drop` impl.
+        match &mut self {
+            Self::Func(f) => Cont::resume(cont, std::mem::replace(f, Func::V)),
+            Self::App(operator, operand) => {
+                Task::Eval(operator.clone(), p(Cont::App1(operand.clone(), cont)))
+            }
+        }
+    }
+
+    /// Evaluate `e`, without copying it if nothing else has a reference to it.
+    fn eval_p(mut e: P<Expr>, cont: P<Cont>) -> Task {
+        match P::get_mut(&mut e) {
+            Some(e) => std::mem::replace(e, Expr::Func(Func::V)).eval(cont),
+            None => (*e).clone().eval(cont),
         }
     }
 }
 
+impl std::fmt::Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write_source(f, Source::Expr(self))
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum Cont {
     /// `(operand, cont)`:
@@ -141,12 +315,31 @@ pub enum Cont {
 }
 
 impl Cont {
+    /// The continuation this one returns to once it's done, if any.
+    pub fn parent(&self) -> Option<&P<Cont>> {
+        match self {
+            Self::App1(_, cont) | Self::App(_, cont) | Self::Del(_, cont) => Some(cont),
+            Self::Final => None,
+        }
+    }
+
     pub fn invoke(&self, val: Func) -> Task {
         match self {
             Self::App1(operand, cont) => Task::App1(val, operand.clone(), cont.clone()),
             Self::App(operator, cont) => Task::App(operator.clone(), val, cont.clone()),
             Self::Del(operand, cont) => Task::App(val, operand.clone(), cont.clone()),
-            Self::Final => Task::Final,
+            Self::Final => Task::Final(val.unlocated()),
+        }
+    }
+
+    /// Like [`Cont::invoke`], but if nothing else has a reference to `this`,
+    /// the function waiting in it is moved out, since cloning it would copy
+    /// everything it holds.
+    pub(crate) fn resume(mut this: P<Cont>, val: Func) -> Task {
+        match P::get_mut(&mut this) {
+            Some(Self::App(f, cont)) => Task::App(std::mem::replace(f, Func::V), val, cont.clone()),
+            Some(Self::Del(f, cont)) => Task::App(val, std::mem::replace(f, Func::V), cont.clone()),
+            _ => this.invoke(val),
         }
     }
 }
@@ -158,19 +351,206 @@ pub enum Task {
     App1(Func, P<Expr>, P<Cont>),
     /// (operator, operand, cont)
     App(Func, Func, P<Cont>),
-    Final,
+    /// The program is done, either by reaching the end or by calling `e`, and
+    /// this is the value it produced. It's never [`OpFunc::Located`], so it's
+    /// the same whether or not the program was parsed with
+    /// [`ParseOptions::locate_io`].
+    Final(Func),
 }
 
 impl Task {
-    pub fn run(self, ctx: &mut Ctx) -> Result<Option<Task>, Error> {
+    /// The continuation that receives the result of this task.
+    pub fn cont(&self) -> Option<&P<Cont>> {
         match self {
-            Self::Eval(expr, cont) => Ok(Some((&*expr).clone().eval(cont))),
-            Self::App1(Func::D, operand, cont) => Ok(Some(cont.invoke(Func::d1(operand)))),
+            Self::Eval(_, cont) | Self::App1(_, _, cont) | Self::App(_, _, cont) => Some(cont),
+            Self::Final(_) => None,
+        }
+    }
+
+    /// True if running this task will read a character of input.
+    pub fn reads_input(&self) -> bool {
+        matches!(self, Self::App(f, _, _) if matches!(f.location().0, Func::At))
+    }
+
+    pub fn run(self, ctx: &mut Ctx<'_>) -> Result<Option<Task>, Error> {
+        match self {
+            Self::Final(_) => Ok(None),
+            task => task.step(ctx).map(Some),
+        }
+    }
+
+    /// Like [`Task::run`], but a `Final` task just steps to itself.
+    pub fn step(self, ctx: &mut Ctx<'_>) -> Result<Task, Error> {
+        self.try_step(ctx).map_err(|(e, _)| e)
+    }
+
+    /// Like [`Task::step`], but on failure also gives back the task, which is
+    /// as it was before the step. See [`Func::try_apply`].
+    pub(crate) fn try_step(self, ctx: &mut Ctx<'_>) -> Result<Task, (Error, Task)> {
+        Ok(match self {
+            Self::Eval(expr, cont) => Expr::eval_p(expr, cont),
+            Self::App1(Func::D, operand, cont) => Cont::resume(cont, Func::d1(operand)),
             Self::App1(operator, operand, cont) => {
-                Ok(Some((&*operand).clone().eval(p(Cont::App(operator, cont)))))
+                Expr::eval_p(operand, p(Cont::App(operator, cont)))
+            }
+            Self::App(operator, operand, cont) => return operator.try_apply(ctx, operand, cont),
+            Self::Final(v) => Self::Final(v),
+        })
+    }
+}
+
+// The compiler's drop glue for these types recurses once per level of nesting,
+// which overflows the stack on the kind of deep trees unlambda programs like to
+// build. Instead, the `Drop` impls move any children they own uniquely onto an
+// explicit stack, and each of those has its children moved there in turn
+// before it's freed.
+
+enum Child {
+    Expr(P<Expr>),
+    Cont(P<Cont>),
+    Op(Box<OpFunc>),
+}
+
+/// Children waiting to be torn down. There's usually at most one, so that's
+/// kept inline to avoid allocating.
+#[derive(Default)]
+struct Stack {
+    top: Option<Child>,
+    rest: Vec<Child>,
+}
+
+impl Stack {
+    fn push(&mut self, c: Child) {
+        if let Some(prev) = self.top.replace(c) {
+            self.rest.push(prev);
+        }
+    }
+
+    fn pop(&mut self) -> Option<Child> {
+        self.top.take().or_else(|| self.rest.pop())
+    }
+}
+
+thread_local! {
+    // Stand-ins for children that have been moved to the stack.
+    static EXPR_HOLE: P<Expr> = P::untracked(Expr::Func(Func::V));
+    static CONT_HOLE: P<Cont> = P::untracked(Cont::Final);
+}
+
+fn take_expr(e: &mut P<Expr>, out: &mut Stack) {
+    // Leaves can't recurse, so leave them be. They're most of what we drop.
+    let leaf = matches!(&**e, Expr::Func(f) if !matches!(f, Func::Op(_)));
+    if !leaf && P::is_unique(e) {
+        if let Ok(e) = EXPR_HOLE.try_with(|h| std::mem::replace(e, h.clone())) {
+            out.push(Child::Expr(e));
+        }
+    }
+}
+
+fn take_cont(c: &mut P<Cont>, out: &mut Stack) {
+    if !matches!(**c, Cont::Final) && P::is_unique(c) {
+        if let Ok(c) = CONT_HOLE.try_with(|h| std::mem::replace(c, h.clone())) {
+            out.push(Child::Cont(c));
+        }
+    }
+}
+
+fn take_func(f: &mut Func, out: &mut Stack) {
+    let shallow = |f: &Func| !matches!(f, Func::Op(_));
+    let leaf = |o: &OpFunc| match o {
+        OpFunc::K1(x) | OpFunc::S1(x) | OpFunc::Located(x, _) => shallow(x),
+        OpFunc::S2(x, y) => shallow(x) && shallow(y),
+        OpFunc::Cont(_) | OpFunc::D1(_) => false,
+    };
+    if matches!(f, Func::Op(o) if !leaf(o)) {
+        if let Func::Op(o) = std::mem::replace(f, Func::V) {
+            out.push(Child::Op(o));
+        }
+    }
+}
+
+impl Expr {
+    fn take_children(&mut self, out: &mut Stack) {
+        match self {
+            Self::Func(f) => take_func(f, out),
+            Self::App(operator, operand) => {
+                take_expr(operator, out);
+                take_expr(operand, out);
+            }
+        }
+    }
+}
+
+impl Cont {
+    fn take_children(&mut self, out: &mut Stack) {
+        match self {
+            Self::App1(operand, cont) => {
+                take_expr(operand, out);
+                take_cont(cont, out);
+            }
+            Self::App(f, cont) | Self::Del(f, cont) => {
+                take_func(f, out);
+                take_cont(cont, out);
+            }
+            Self::Final => {}
+        }
+    }
+}
+
+impl OpFunc {
+    fn take_children(&mut self, out: &mut Stack) {
+        match self {
+            Self::K1(x) | Self::S1(x) | Self::Located(x, _) => take_func(x, out),
+            Self::S2(x, y) => {
+                take_func(x, out);
+                take_func(y, out);
+            }
+            Self::Cont(c) => take_cont(c, out),
+            Self::D1(e) => take_expr(e, out),
+        }
+    }
+}
+
+fn teardown(mut stack: Stack) {
+    while let Some(child) = stack.pop() {
+        // Whatever we pop is dropped at the end of the iteration, after its
+        // children have been moved out, so its own `Drop` has nothing to do.
+        match child {
+            Child::Expr(mut e) => {
+                if let Some(e) = P::get_mut(&mut e) {
+                    e.take_children(&mut stack);
+                }
+            }
+            Child::Cont(mut c) => {
+                if let Some(c) = P::get_mut(&mut c) {
+                    c.take_children(&mut stack);
+                }
             }
-            Self::App(operator, operand, cont) => operator.apply_to(ctx, operand, cont).map(Some),
-            Self::Final => Ok(None),
+            Child::Op(mut o) => o.take_children(&mut stack),
         }
     }
 }
+
+impl Drop for Expr {
+    fn drop(&mut self) {
+        let mut stack = Stack::default();
+        self.take_children(&mut stack);
+        teardown(stack);
+    }
+}
+
+impl Drop for Cont {
+    fn drop(&mut self) {
+        let mut stack = Stack::default();
+        self.take_children(&mut stack);
+        teardown(stack);
+    }
+}
+
+impl Drop for OpFunc {
+    fn drop(&mut self) {
+        let mut stack = Stack::default();
+        self.take_children(&mut stack);
+        teardown(stack);
+    }
+}
@@ -1,119 +1,612 @@
 //! Various input and output types.
 
 use super::*;
+use crate::transcript::{Action, Divergence, Event, Recording, Transcript};
 use std::{
     char::REPLACEMENT_CHARACTER,
+    convert::TryFrom,
+    fs::File,
     io::{ErrorKind, Read, Stdout, Write},
     path::Path,
+    time::Instant,
 };
 
+/// How often (in steps) we check for timeouts and cancellation.
+const CHECK_INTERVAL: u64 = 1024;
+
+/// How characters are read from a program's input and written to its output.
+/// See [`EvalOptions::encoding`].
+#[derive(Default, Copy, Clone, Debug, PartialEq, Eq)]
 #[non_exhaustive]
-pub enum Output {
+pub enum Encoding {
+    /// Input is decoded as UTF-8, with invalid sequences read as
+    /// [`REPLACEMENT_CHARACTER`]. Output is encoded as UTF-8.
+    #[default]
+    Utf8,
+    /// Each byte of input is read as the character with that value (U+0000 to
+    /// U+00FF), and those characters are written back out as a single byte,
+    /// so programs can pass binary data through unchanged. Anything above
+    /// U+00FF is still written as UTF-8.
+    Latin1,
+}
+
+/// What to do with input that isn't valid UTF-8 (when using
+/// [`Encoding::Utf8`]). See [`EvalOptions::invalid_utf8`].
+#[derive(Default, Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Utf8Policy {
+    /// Read each invalid sequence as a [`REPLACEMENT_CHARACTER`]. As it always
+    /// has, a sequence that's cut short takes the bytes after it with it, up
+    /// to the length its first byte says it has: `\xc3(` is read as a single
+    /// U+FFFD.
+    #[default]
+    Replace,
+    /// Stop with [`Error::InvalidUtf8`].
+    Error,
+    /// Ignore invalid sequences, as if they weren't there.
+    Skip,
+}
+
+/// Where an unlambda program's output goes.
+#[non_exhaustive]
+pub enum Output<'a> {
     Buffer(Vec<u8>),
-    Writer(Box<dyn Write + Sync + Send + 'static>),
+    /// Doesn't need to be `'static`, so e.g. a `&mut Vec<u8>` is fine. It does
+    /// need to be `Send`, so that a [`Ctx`] can move between threads. See
+    /// [`Output::writer`], and [`eval_to_writer`] for writers that aren't
+    /// `Send`, like a locked stdout.
+    Writer(Box<dyn Write + Send + 'a>),
+    /// Called with each character as the program prints it. See
+    /// [`Output::callback`].
+    Callback(Box<dyn FnMut(char) -> std::io::Result<()> + Send + 'a>),
 }
-impl Output {
-    fn putc(&mut self, c: char) -> std::io::Result<()> {
+
+impl<'a> Output<'a> {
+    pub fn writer(w: impl Write + Send + 'a) -> Self {
+        Self::Writer(Box::new(w))
+    }
+
+    /// Send output to `f` one character at a time. If `f` returns an error,
+    /// evaluation stops with [`Error::Io`].
+    pub fn callback(f: impl FnMut(char) -> std::io::Result<()> + Send + 'a) -> Self {
+        Self::Callback(Box::new(f))
+    }
+
+    fn putc(&mut self, c: char, enc: Encoding) -> std::io::Result<()> {
         let mut a = [0u8; 4];
-        let bytes = c.encode_utf8(&mut a).as_bytes();
+        let bytes = encode(c, enc, &mut a);
         match self {
-            Self::Buffer(b) => b.extend_from_slice(&bytes),
-            Self::Writer(w) => w.write_all(&bytes)?,
+            Self::Buffer(b) => b.extend_from_slice(bytes),
+            Self::Writer(w) => w.write_all(bytes)?,
+            Self::Callback(f) => f(c)?,
         }
         Ok(())
     }
 }
 
-impl From<Vec<u8>> for Output {
+fn encode(c: char, enc: Encoding, buf: &mut [u8; 4]) -> &[u8] {
+    match u8::try_from(c) {
+        Ok(b) if enc == Encoding::Latin1 => {
+            buf[0] = b;
+            &buf[..1]
+        }
+        _ => c.encode_utf8(buf).as_bytes(),
+    }
+}
+
+/// Whether a program may do IO. See [`EvalOptions::io`].
+#[derive(Default, Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum IoPolicy {
+    #[default]
+    Allow,
+    /// Applying `.x`, `r`, `@`, `?x` or `|` stops evaluation with
+    /// [`Error::IoNotPermitted`]. The `eval_*` functions parse the program
+    /// with [`ParseOptions::locate_io`], so the error says where the builtin
+    /// is; do the same when running a program on a [`Ctx`] yourself.
+    Deny,
+    /// IO happens, but on the ctx's [`VirtualDevice`] rather than its real
+    /// input and output.
+    Virtual,
+}
+
+/// In-memory input and output for a program run with [`IoPolicy::Virtual`].
+/// See [`Ctx::device`].
+#[derive(Default, Clone, Debug, PartialEq)]
+pub struct VirtualDevice {
+    /// What the program reads.
+    pub input: Vec<u8>,
+    /// How much of `input` the program has read.
+    pub read: usize,
+    /// What the program has written.
+    pub output: Vec<u8>,
+}
+
+impl VirtualDevice {
+    fn read_byte(&mut self) -> Option<u8> {
+        let b = self.input.get(self.read).copied();
+        self.read += b.is_some() as usize;
+        b
+    }
+}
+
+impl From<Vec<u8>> for Output<'_> {
     fn from(v: Vec<u8>) -> Self {
         Self::Buffer(v)
     }
 }
-impl From<Box<dyn Write + Sync + Send + 'static>> for Output {
+impl<'a> From<Box<dyn Write + Sync + Send + 'static>> for Output<'a> {
     fn from(w: Box<dyn Write + Sync + Send + 'static>) -> Self {
         Self::Writer(w)
     }
 }
-impl From<Stdout> for Output {
+impl<'a> From<Box<dyn Write + Send + 'a>> for Output<'a> {
+    fn from(w: Box<dyn Write + Send + 'a>) -> Self {
+        Self::Writer(w)
+    }
+}
+impl<'a> From<&'a mut Vec<u8>> for Output<'a> {
+    fn from(v: &'a mut Vec<u8>) -> Self {
+        Self::Writer(Box::new(v))
+    }
+}
+impl From<Stdout> for Output<'_> {
     fn from(o: Stdout) -> Self {
         Self::Writer(Box::new(o))
     }
 }
 
-pub struct Ctx {
-    pub(crate) stdin: Box<dyn std::io::Read + Send + 'static>,
-    pub(crate) stdout: Output,
+pub struct Ctx<'a> {
+    pub(crate) stdin: Box<dyn Read + Send + 'a>,
+    pub(crate) stdout: Output<'a>,
     last_char: Option<char>,
+    pub(crate) lookahead: Lookahead,
+    recording: Option<Recording>,
+    device: VirtualDevice,
+    opts: EvalOptions,
+    steps: u64,
+    chars_written: u64,
+    deadline: Option<Instant>,
+    live: LiveObjects,
 }
 
-impl Ctx {
+impl<'a> Ctx<'a> {
     pub fn new<I, O>(stdin: I, stdout: O) -> Self
     where
-        I: Read + 'static + Sync + Send,
-        O: Into<Output>,
+        I: Read + Send + 'a,
+        O: Into<Output<'a>>,
+    {
+        Self::with_options(stdin, stdout, EvalOptions::default())
+    }
+
+    pub fn with_options<I, O>(stdin: I, stdout: O, opts: EvalOptions) -> Self
+    where
+        I: Read + Send + 'a,
+        O: Into<Output<'a>>,
     {
         Self {
             stdin: Box::new(stdin),
             stdout: stdout.into(),
             last_char: None,
+            lookahead: Lookahead::default(),
+            recording: None,
+            device: VirtualDevice::default(),
+            opts,
+            steps: 0,
+            chars_written: 0,
+            deadline: None,
+            live: LiveObjects::default(),
         }
     }
 
+    pub fn options(&self) -> &EvalOptions {
+        &self.opts
+    }
+
+    pub fn options_mut(&mut self) -> &mut EvalOptions {
+        &mut self.opts
+    }
+
+    /// Where the program's output goes. If it's an [`Output::Buffer`], this is
+    /// how you get at what the program printed.
+    pub fn output(&self) -> &Output<'a> {
+        &self.stdout
+    }
+
+    /// The number of steps taken by the current (or most recent) call to
+    /// [`Ctx::execute`] or [`Ctx::evaluate`].
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Reset the per-evaluation state (step count, output count, deadline)
+    /// before running a new program.
+    pub(crate) fn begin(&mut self) {
+        self.steps = 0;
+        self.chars_written = 0;
+        self.deadline = self.opts.timeout.map(|t| Instant::now() + t);
+    }
+
+    /// If we're limiting live objects, start counting them. Objects allocated
+    /// before the returned scope is dropped are counted.
+    pub(crate) fn track(&self) -> Option<TrackingScope> {
+        self.opts.max_objects.map(|_| self.live.track())
+    }
+
+    /// Apply `f` to `arg`, as in `` `f arg ``. This is how you call a function
+    /// that an unlambda program returned, e.g. from [`Ctx::evaluate`].
+    pub fn apply(&mut self, f: Func, arg: Func) -> Result<Func, Error> {
+        let e = Expr::App(p(Expr::Func(f)), p(Expr::Func(arg)));
+        self.evaluate(p(e))
+    }
+
+    /// Called once per step of the interpreter loop, to enforce the limits in
+    /// our [`EvalOptions`].
+    pub(crate) fn tick(&mut self) -> Result<(), Error> {
+        if let Some(max) = self.opts.max_steps {
+            if self.steps >= max {
+                return Err(Error::StepLimit(self.steps));
+            }
+        }
+        if let Some(max) = self.opts.max_objects {
+            let live = self.live.count();
+            if live > max {
+                return Err(Error::ObjectLimit(live));
+            }
+        }
+        if self.steps.is_multiple_of(CHECK_INTERVAL) {
+            if self.opts.cancel.as_ref().is_some_and(|c| c.is_cancelled()) {
+                return Err(Error::Cancelled);
+            }
+            if self.deadline.is_some_and(|d| Instant::now() >= d) {
+                return Err(Error::TimedOut);
+            }
+        }
+        self.steps += 1;
+        Ok(())
+    }
+
     pub(crate) fn putc(&mut self, c: char) -> Result<(), Error> {
-        self.stdout.putc(c).map_err(Into::into)
+        if self
+            .opts
+            .max_output
+            .is_some_and(|max| self.chars_written >= max)
+        {
+            return Err(Error::OutputLimit(vec![]));
+        }
+        if let Some(r @ Recording::Replay(..)) = &mut self.recording {
+            r.expect(self.steps, Action::Write(c))?;
+        }
+        if self.opts.io == IoPolicy::Virtual {
+            let mut a = [0u8; 4];
+            let bytes = encode(c, self.opts.encoding, &mut a);
+            self.device.output.extend_from_slice(bytes);
+        } else {
+            self.stdout.putc(c, self.opts.encoding)?;
+        }
+        // Only now, so that a failed write can be retried (see `Machine`).
+        self.chars_written += 1;
+        if let Some(Recording::Record(t)) = &mut self.recording {
+            t.push(self.steps, Event::Write(c));
+        }
+        Ok(())
     }
 
     pub(crate) fn getc(&mut self) -> Result<Option<char>, Error> {
-        let c = Self::read_single_char(&mut self.stdin)?;
+        let c = match &mut self.recording {
+            Some(r @ Recording::Replay(..)) => match r.expect(self.steps, Action::Read)? {
+                Event::Read(c) => c,
+                Event::Write(_) => unreachable!(),
+            },
+            _ => {
+                while !self.input_ready()? {
+                    let b = match self.opts.io {
+                        IoPolicy::Virtual => self.device.read_byte(),
+                        _ => Self::read_byte(&mut self.stdin)?,
+                    };
+                    self.lookahead.push(b);
+                }
+                self.lookahead.take()
+            }
+        };
+        if let Some(Recording::Record(t)) = &mut self.recording {
+            t.push(self.steps, Event::Read(c));
+        }
         self.last_char = c;
         Ok(c)
     }
 
+    /// True if running `task` will read from our real input, so a driver that
+    /// supplies the input itself (like [`eval_async`] or a pipeline) has to
+    /// get the next char ready first. Never true with an [`IoPolicy`] other
+    /// than `Allow`, since then the program doesn't get to touch it.
+    ///
+    /// [`eval_async`]: crate::future::eval_async
+    pub(crate) fn needs_input(&self, task: &Task) -> bool {
+        self.opts.io == IoPolicy::Allow && task.reads_input()
+    }
+
+    /// Fails if the program isn't allowed to apply `f` because of
+    /// [`EvalOptions::io`]. Every builtin is applied through
+    /// [`Func::apply_to`], which calls this first, whatever is driving the
+    /// evaluation.
+    pub(crate) fn check_io(&self, f: &Func) -> Result<(), Error> {
+        if self.opts.io == IoPolicy::Deny && f.is_io() {
+            let (f, span) = f.location();
+            Err(Error::IoNotPermitted(IoNotPermitted {
+                builtin: f.to_string(),
+                step: self.steps,
+                span,
+            }))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// The in-memory device that IO goes to with [`IoPolicy::Virtual`]. Set
+    /// its `input` before running the program, and look at its `output`
+    /// afterwards.
+    pub fn device(&self) -> &VirtualDevice {
+        &self.device
+    }
+
+    pub fn device_mut(&mut self) -> &mut VirtualDevice {
+        &mut self.device
+    }
+
+    /// Start recording everything the program reads and writes. See
+    /// [`transcript`].
+    pub fn record(&mut self) {
+        self.recording = Some(Recording::Record(Transcript::new()));
+    }
+
+    /// Stop recording, and return what was recorded since [`Ctx::record`].
+    pub fn take_transcript(&mut self) -> Option<Transcript> {
+        match self.recording.take() {
+            Some(Recording::Record(t)) => Some(t),
+            other => {
+                self.recording = other;
+                None
+            }
+        }
+    }
+
+    /// Replay `t`: input comes from the transcript instead of stdin, and
+    /// output is checked against it (as well as being written as usual).
+    /// Evaluation stops with [`Error::Diverged`] as soon as the program does
+    /// something the transcript doesn't have next.
+    pub fn replay(&mut self, t: Transcript) {
+        self.recording = Some(Recording::Replay(t, 0));
+    }
+
+    /// Stop replaying. Fails with [`Error::Diverged`] if the program didn't get
+    /// through the whole transcript.
+    pub fn finish_replay(&mut self) -> Result<(), Error> {
+        match self.recording.take() {
+            Some(Recording::Replay(t, pos)) if pos < t.events().len() => {
+                Err(Error::Diverged(Divergence {
+                    step: self.steps,
+                    expected: Some(t.events()[pos]),
+                    found: Action::End,
+                }))
+            }
+            Some(Recording::Record(t)) => {
+                self.recording = Some(Recording::Record(t));
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Decode as much of the input we've read so far as possible. Returns true
+    /// if the next [`Ctx::getc`] won't have to read any more.
+    pub(crate) fn input_ready(&mut self) -> Result<bool, Error> {
+        if let Some(Recording::Replay(..)) = self.recording {
+            return Ok(true);
+        }
+        let (enc, policy) = (self.opts.encoding, self.opts.invalid_utf8);
+        self.lookahead.decode(enc, policy)
+    }
+
     pub(crate) fn last_char(&self) -> Option<char> {
         self.last_char
     }
+
+    pub(crate) fn set_last_char(&mut self, c: Option<char>) {
+        self.last_char = c;
+    }
+    /// Run `expr` to completion. See [`Ctx::evaluate`] if you care about the
+    /// value it produces, and not just its side effects.
     pub fn execute(&mut self, expr: P<Expr>) -> Result<(), Error> {
+        self.evaluate(expr).map(drop)
+    }
+
+    /// Like [`Ctx::execute`], but returns the value the program finished with:
+    /// the argument passed to `e`, or whatever the whole program reduced to.
+    pub fn evaluate(&mut self, expr: P<Expr>) -> Result<Func, Error> {
+        self.evaluate_with(expr, |_| Ok(()))
+    }
+
+    /// Like [`Ctx::evaluate`], but calls `after_step` after every step.
+    pub(crate) fn evaluate_with(
+        &mut self,
+        expr: P<Expr>,
+        mut after_step: impl FnMut(&mut Self) -> Result<(), Error>,
+    ) -> Result<Func, Error> {
+        self.begin();
+        let _tracking = self.track();
         let mut task = Task::Eval(expr, p(Cont::Final));
-        while let Some(t) = task.run(self)? {
-            task = t;
+        loop {
+            task = match task {
+                Task::Final(v) => return Ok(v),
+                t => {
+                    self.tick()?;
+                    let t = t.step(self)?;
+                    after_step(self)?;
+                    t
+                }
+            };
+        }
+    }
+
+    /// Write anything in our [`Output::Buffer`] to `out`, and empty it.
+    pub(crate) fn drain_output(&mut self, out: &mut impl Write) -> Result<(), Error> {
+        if let Output::Buffer(b) = &mut self.stdout {
+            if !b.is_empty() {
+                out.write_all(b)?;
+                b.clear();
+            }
         }
         Ok(())
     }
 
-    fn read_single_char(mut r: impl Read) -> Result<Option<char>, Error> {
-        let mut first = 0u8;
-        if let Err(e) = r.read_exact(std::slice::from_mut(&mut first)) {
-            return if e.kind() == ErrorKind::UnexpectedEof {
-                Ok(None)
-            } else {
-                Err(e.into())
-            };
+    fn read_byte(mut r: impl Read) -> Result<Option<u8>, Error> {
+        let mut b = 0u8;
+        match r.read_exact(std::slice::from_mut(&mut b)) {
+            Ok(()) => Ok(Some(b)),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(None),
+            Err(e) => Err(e.into()),
         }
+    }
+}
 
-        let width = match utf8_char_width(first) {
-            1 => return Ok(Some(first as char)),
-            0 => return Ok(Some(REPLACEMENT_CHARACTER)),
-            n => n as usize,
-        };
+/// The error for a program that tried to do IO with [`IoPolicy::Deny`].
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct IoNotPermitted {
+    /// The builtin the program applied, as it'd be written in source, e.g.
+    /// `.x`.
+    pub builtin: String,
+    /// The step it happened on.
+    pub step: u64,
+    /// Where the builtin is in the source, if the program was parsed with
+    /// [`ParseOptions::locate_io`].
+    pub span: Option<Span>,
+}
 
-        let mut buf = [first, 0, 0, 0];
-        if let Err(e) = r.read_exact(&mut buf[1..width]) {
-            return if e.kind() == ErrorKind::UnexpectedEof {
-                return Ok(Some(REPLACEMENT_CHARACTER));
-            } else {
-                Err(e.into())
-            };
+impl std::fmt::Display for IoNotPermitted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "IO not permitted: `{}` applied at step {}",
+            self.builtin, self.step
+        )?;
+        if let Some(s) = self.span {
+            write!(f, " (line {}, column {})", s.line, s.col)?;
         }
+        Ok(())
+    }
+}
 
-        Ok(Some(
-            std::str::from_utf8(&buf[..width])
-                .ok()
-                .and_then(|s| s.chars().next())
-                .unwrap_or(REPLACEMENT_CHARACTER),
-        ))
+/// Input bytes that have been read but not yet returned to the program as a
+/// character. Decoding goes a byte at a time, so that it can be driven by a
+/// blocking [`Read`] ([`Ctx::getc`]) or by something that might not have the
+/// next byte yet (see [`future`]).
+#[derive(Default, Debug)]
+pub(crate) struct Lookahead {
+    bytes: Vec<u8>,
+    /// The offset of `bytes[0]` in the input.
+    offset: u64,
+    eof: bool,
+    /// The next character, once we've decoded it. `Some(None)` at EOF.
+    ready: Option<Option<char>>,
+}
+
+impl Lookahead {
+    /// Add the next byte of input, or `None` for EOF.
+    pub(crate) fn push(&mut self, b: Option<u8>) {
+        match b {
+            Some(b) => self.bytes.push(b),
+            None => self.eof = true,
+        }
+    }
+
+    fn consume(&mut self, n: usize) {
+        self.bytes.drain(..n);
+        self.offset += n as u64;
+    }
+
+    fn take(&mut self) -> Option<char> {
+        self.ready
+            .take()
+            .expect("take() before the next char was decoded")
+    }
+
+    /// Try to decode the next character. Returns false if another byte is
+    /// needed first.
+    fn decode(&mut self, enc: Encoding, policy: Utf8Policy) -> Result<bool, Error> {
+        while self.ready.is_none() {
+            let start = self.offset;
+            match self.next(enc, policy) {
+                Next::Char(c, len) => {
+                    self.consume(len);
+                    self.ready = Some(Some(c));
+                }
+                Next::Eof => self.ready = Some(None),
+                Next::Incomplete => return Ok(false),
+                Next::Invalid(len) => {
+                    self.consume(len);
+                    match policy {
+                        Utf8Policy::Replace => self.ready = Some(Some(REPLACEMENT_CHARACTER)),
+                        Utf8Policy::Error => return Err(Error::InvalidUtf8(start)),
+                        Utf8Policy::Skip => {}
+                    }
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    /// What's at the front of `bytes`.
+    fn next(&self, enc: Encoding, policy: Utf8Policy) -> Next {
+        let first = match self.bytes.first() {
+            Some(&b) => b,
+            None if self.eof => return Next::Eof,
+            None => return Next::Incomplete,
+        };
+        if enc == Encoding::Latin1 {
+            return Next::Char(char::from(first), 1);
+        }
+        let width = utf8_char_width(first);
+        if width == 0 {
+            return Next::Invalid(1);
+        }
+        let have = width.min(self.bytes.len());
+        // How many bytes (at least one) are part of this character.
+        let len = 1 + self.bytes[1..have]
+            .iter()
+            .take_while(|&&b| b & 0b1100_0000 == 0b1000_0000)
+            .count();
+        if len == width {
+            return match std::str::from_utf8(&self.bytes[..len]) {
+                Ok(s) => Next::Char(s.chars().next().unwrap(), len),
+                Err(_) => Next::Invalid(len),
+            };
+        }
+        match policy {
+            // It's fine so far, we just don't have all of it yet.
+            _ if len == self.bytes.len() && !self.eof => Next::Incomplete,
+            // The whole `width` bytes go, whatever they are.
+            Utf8Policy::Replace if have < width && !self.eof => Next::Incomplete,
+            Utf8Policy::Replace => Next::Invalid(have),
+            // Anything after `len` might be the start of the next character,
+            // so leave it.
+            _ => Next::Invalid(len),
+        }
     }
 }
 
+/// See [`Lookahead::next`].
+enum Next {
+    /// A character, and how many bytes it takes up.
+    Char(char, usize),
+    /// An invalid sequence this many bytes long.
+    Invalid(usize),
+    Eof,
+    /// We can't tell until we have more bytes.
+    Incomplete,
+}
+
 fn utf8_char_width(first_byte: u8) -> usize {
     match first_byte {
         0b0000_0000..=0b0111_1111 => 1,
@@ -129,22 +622,64 @@ fn utf8_char_width(first_byte: u8) -> usize {
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Input<'a> {
     Str(&'a str),
+    /// Doesn't need to be UTF-8. Invalid sequences are read as
+    /// [`REPLACEMENT_CHARACTER`] by the program (and are an error when parsing
+    /// source code).
+    Bytes(&'a [u8]),
     File(&'a Path),
     Stdin,
 }
 
-impl Input<'_> {
+impl<'a> Input<'a> {
     pub fn parse(&self) -> Result<P<Expr>, ParseError> {
+        self.parse_for(&EvalOptions::default())
+    }
+
+    /// Parse the program to run with `opts`. With [`IoPolicy::Deny`], its IO
+    /// builtins know where they are, so the error can point at them.
+    pub(crate) fn parse_for(&self, opts: &EvalOptions) -> Result<P<Expr>, ParseError> {
         let o = crate::ParseOptions {
             log_warnings: true,
             strict: false,
+            locate_io: opts.io == IoPolicy::Deny,
         };
         match self {
-            Self::Str(s) => parse_from_str(*s, o),
+            Self::Str(s) => parse_from_str(s, o),
+            Self::Bytes(b) => parse_from_reader(*b, o),
             Self::File(s) => parse_from_file(*s, o),
             Self::Stdin => parse_from_stdin(o),
         }
     }
+
+    /// Open this input for reading, e.g. to hand it to [`Ctx::new`] as the
+    /// program's stdin.
+    pub fn reader(&self) -> std::io::Result<Box<dyn Read + Sync + Send + 'a>> {
+        Ok(match *self {
+            Self::Str(s) => Box::new(s.as_bytes()),
+            Self::Bytes(b) => Box::new(b),
+            Self::File(s) => Box::new(File::open(s)?),
+            Self::Stdin => Box::new(std::io::stdin()),
+        })
+    }
+}
+
+/// Anything that can be the "stdin" of a program run by [`eval_to_string`] and
+/// friends: an [`Input`], or any [`Read`] that's `Send`, like a socket or a
+/// `Cursor<Vec<u8>>`.
+pub trait ProgramInput<'a> {
+    fn into_reader(self) -> std::io::Result<Box<dyn Read + Send + 'a>>;
+}
+
+impl<'a> ProgramInput<'a> for Input<'a> {
+    fn into_reader(self) -> std::io::Result<Box<dyn Read + Send + 'a>> {
+        Ok(self.reader()?)
+    }
+}
+
+impl<'a, R: Read + Send + 'a> ProgramInput<'a> for R {
+    fn into_reader(self) -> std::io::Result<Box<dyn Read + Send + 'a>> {
+        Ok(Box::new(self))
+    }
 }
 
 impl<'a> From<&'a str> for Input<'a> {
@@ -153,6 +688,12 @@ impl<'a> From<&'a str> for Input<'a> {
     }
 }
 
+impl<'a> From<&'a [u8]> for Input<'a> {
+    fn from(b: &'a [u8]) -> Self {
+        Self::Bytes(b)
+    }
+}
+
 impl<'a> From<&'a Path> for Input<'a> {
     fn from(s: &'a Path) -> Self {
         Self::File(s)
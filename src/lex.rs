@@ -0,0 +1,113 @@
+//! Splitting source into tokens, without throwing anything away: comments,
+//! whitespace, and anything invalid are tokens too, so the tokens of some
+//! source cover all of it. Handy for syntax highlighting.
+//!
+//! Note that after a `.` or `?`, the next char is taken literally even if
+//! it's whitespace or a `#`, so `.#` is a [`TokenKind::Dot`] and not the
+//! start of a comment.
+
+use super::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TokenKind {
+    Backtick,
+    /// One of the single-char builtins, as written (so possibly uppercase).
+    Builtin(char),
+    /// `.x`
+    Dot(char),
+    /// `?x`
+    Question(char),
+    /// From a `#` up to (but not including) the end of the line.
+    Comment,
+    Whitespace,
+    /// A char that can't start anything, or a `.` or `?` at the end of the
+    /// input.
+    Invalid,
+}
+
+impl TokenKind {
+    /// True for comments and whitespace, which the parser skips.
+    pub fn is_trivia(self) -> bool {
+        matches!(self, Self::Comment | Self::Whitespace)
+    }
+
+    /// The builtin this stands for, if it's a builtin, `.x`, or `?x`.
+    pub fn func(self) -> Option<Func> {
+        match self {
+            Self::Builtin(c) => builtin(c),
+            Self::Dot(c) => Some(Func::Dot(c)),
+            Self::Question(c) => Some(Func::Q(c)),
+            _ => None,
+        }
+    }
+}
+
+/// A token and where it is. Unlike a [`cst::Token`], trivia is its own
+/// lexeme rather than being attached to the next token.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Lexeme {
+    pub kind: TokenKind,
+    /// Where the token is in the source, in bytes.
+    pub range: std::ops::Range<usize>,
+}
+
+/// An iterator over the tokens of some source. See [`tokenize`].
+#[derive(Clone, Debug)]
+pub struct Tokens<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+/// The tokens of `s`, in order.
+pub fn tokenize(s: &str) -> Tokens<'_> {
+    tokenize_from(s, 0)
+}
+
+/// The tokens of `s` starting at byte `pos`.
+pub(crate) fn tokenize_from(s: &str, pos: usize) -> Tokens<'_> {
+    Tokens { input: s, pos }
+}
+
+impl Tokens<'_> {
+    fn bump(&mut self) -> Option<char> {
+        let c = self.input[self.pos..].chars().next()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    /// Move past chars for as long as `f` holds.
+    fn bump_while(&mut self, f: impl Fn(char) -> bool) {
+        let rest = &self.input[self.pos..];
+        self.pos += rest.find(|c| !f(c)).unwrap_or(rest.len());
+    }
+}
+
+impl Iterator for Tokens<'_> {
+    type Item = Lexeme;
+    fn next(&mut self) -> Option<Lexeme> {
+        let start = self.pos;
+        let kind = match self.bump()? {
+            '`' => TokenKind::Backtick,
+            '#' => {
+                self.bump_while(|c| c != '\n');
+                TokenKind::Comment
+            }
+            c if c.is_whitespace() => {
+                self.bump_while(char::is_whitespace);
+                TokenKind::Whitespace
+            }
+            // The next char is taken literally, even if it's a `#` or space.
+            '.' => self.bump().map_or(TokenKind::Invalid, TokenKind::Dot),
+            '?' => self.bump().map_or(TokenKind::Invalid, TokenKind::Question),
+            c if builtin(c).is_some() => TokenKind::Builtin(c),
+            _ => TokenKind::Invalid,
+        };
+        Some(Lexeme {
+            kind,
+            range: start..self.pos,
+        })
+    }
+}
+
+impl std::iter::FusedIterator for Tokens<'_> {}
@@ -4,6 +4,8 @@
 //! I'll get to that but this project does nobody any good sitting in my
 //! `~/code` folder.
 //!
+//! There's now a binary too, in `src/bin/unlambda.rs`; see `unlambda --help`.
+//!
 //! It's rough around the edges and the docs are almost non-existent. The
 //! important parts of the api are exposed at the top level but it's been long
 //! enough since I looked that really I just fixed obvious warts and exposed
@@ -27,19 +29,37 @@
 //! # }
 //! ```
 
-pub use eval::{eval_to_stdout, eval_to_string, eval_to_vec, Error as EvalError};
-pub use io::Input;
-pub use parse::{parse_from_file, parse_from_reader, parse_from_stdin, parse_from_str, ParseError};
+pub use cst::{parse_lossless, SyntaxTree};
+pub use eval::{
+    eval_chars, eval_to_stdout, eval_to_stdout_with, eval_to_string, eval_to_string_with,
+    eval_to_vec, eval_to_vec_with, eval_to_writer, eval_to_writer_with, eval_value,
+    eval_value_with, CancelHandle, Error as EvalError, EvalOptions,
+};
+pub use io::{Encoding, Input, IoPolicy, ProgramInput, Utf8Policy};
+pub use machine::Machine;
+pub use parse::{
+    parse_file_recovering, parse_from_file, parse_from_reader, parse_from_stdin, parse_from_str,
+    parse_recovering, parse_with_spans, ParseError,
+};
+pub use session::Session;
 pub use util::P;
 
+pub mod cst;
 pub mod eval;
+pub mod future;
 pub mod internals;
 pub mod io;
+pub mod lex;
+pub mod machine;
 pub mod parse;
+pub mod pipeline;
+pub mod repl;
+pub mod session;
+pub mod transcript;
 mod util;
 
 pub(crate) use eval::*;
 pub(crate) use internals::*;
 pub(crate) use io::*;
 pub(crate) use parse::*;
-pub(crate) use util::p;
+pub(crate) use util::{p, LiveObjects, TrackingScope};
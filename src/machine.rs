@@ -0,0 +1,203 @@
+//! A steppable interpreter, for when [`Ctx::evaluate`] is too all-or-nothing,
+//! e.g. for debuggers, or for running several programs at once.
+
+use super::*;
+use std::{
+    collections::VecDeque,
+    io::Read,
+    sync::{Arc, Mutex},
+};
+
+/// Owns a [`Ctx`] and the current [`Task`] of a program running on it, and
+/// lets you run the program a bit at a time.
+///
+/// All of the limits in the ctx's [`EvalOptions`] apply, counting from when
+/// the machine was created. Errors (including hitting those limits) leave the
+/// machine in the state it was in before the step that failed, so you can
+/// (for example) raise [`EvalOptions::max_steps`] and keep going.
+pub struct Machine<'a> {
+    ctx: Ctx<'a>,
+    task: Task,
+}
+
+impl<'a> Machine<'a> {
+    pub fn new(mut ctx: Ctx<'a>, expr: P<Expr>) -> Self {
+        ctx.begin();
+        Self {
+            ctx,
+            task: Task::Eval(expr, p(Cont::Final)),
+        }
+    }
+
+    /// Take a single step. Returns `false` if the program had already finished
+    /// so there was nothing to do.
+    pub fn step(&mut self) -> Result<bool, Error> {
+        if self.is_finished() {
+            return Ok(false);
+        }
+        let _tracking = self.ctx.track();
+        self.step_imp()?;
+        Ok(true)
+    }
+
+    /// Take up to `n` steps, stopping early if the program finishes. Returns
+    /// the number of steps taken.
+    pub fn run_for(&mut self, n: u64) -> Result<u64, Error> {
+        let _tracking = self.ctx.track();
+        let mut taken = 0;
+        while taken < n && !self.is_finished() {
+            self.step_imp()?;
+            taken += 1;
+        }
+        Ok(taken)
+    }
+
+    /// Run the program until it finishes, and return its result.
+    pub fn run(&mut self) -> Result<&Func, Error> {
+        let _tracking = self.ctx.track();
+        while !self.is_finished() {
+            self.step_imp()?;
+        }
+        Ok(self.result().expect("loop only exits when finished"))
+    }
+
+    fn step_imp(&mut self) -> Result<(), Error> {
+        self.ctx.tick()?;
+        // A failed step gives the task back untouched, so there's no need to
+        // keep a copy of it.
+        let task = std::mem::replace(&mut self.task, Task::Final(Func::V));
+        match task.try_step(&mut self.ctx) {
+            Ok(task) => {
+                self.task = task;
+                Ok(())
+            }
+            Err((e, task)) => {
+                self.task = task;
+                Err(e)
+            }
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.result().is_some()
+    }
+
+    /// The value the program finished with, if it's finished.
+    pub fn result(&self) -> Option<&Func> {
+        match &self.task {
+            Task::Final(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// The task that will be run on the next step.
+    pub fn task(&self) -> &Task {
+        &self.task
+    }
+
+    /// The continuation stack of the current task, innermost first.
+    pub fn continuations(&self) -> Continuations<'_> {
+        Continuations(self.task.cont().map(|c| &**c))
+    }
+
+    pub fn ctx(&self) -> &Ctx<'a> {
+        &self.ctx
+    }
+
+    pub fn ctx_mut(&mut self) -> &mut Ctx<'a> {
+        &mut self.ctx
+    }
+
+    pub fn into_ctx(self) -> Ctx<'a> {
+        self.ctx
+    }
+}
+
+/// Iterator over a chain of continuations. See [`Machine::continuations`].
+#[derive(Clone, Debug)]
+pub struct Continuations<'a>(Option<&'a Cont>);
+
+impl<'a> Iterator for Continuations<'a> {
+    type Item = &'a Cont;
+    fn next(&mut self) -> Option<&'a Cont> {
+        let c = self.0?;
+        self.0 = c.parent().map(|c| &**c);
+        Some(c)
+    }
+}
+
+/// An iterator over the characters a program prints, which only runs the
+/// program as far as it needs to to produce the next one. See [`eval_chars`].
+///
+/// If the program fails, the error is the last item. With
+/// [`IoPolicy::Virtual`], the program reads from and writes to the ctx's
+/// [`VirtualDevice`] as usual, and this produces what it writes there.
+///
+/// [`IoPolicy::Virtual`]: io::IoPolicy::Virtual
+/// [`VirtualDevice`]: io::VirtualDevice
+pub struct OutputChars<'a> {
+    machine: Machine<'a>,
+    printed: Arc<Mutex<VecDeque<char>>>,
+    failed: bool,
+}
+
+impl<'a> OutputChars<'a> {
+    pub fn new(stdin: impl Read + Send + 'a, expr: P<Expr>, opts: EvalOptions) -> Self {
+        let printed = Arc::new(Mutex::new(VecDeque::new()));
+        let sink = printed.clone();
+        let out = io::Output::callback(move |c| {
+            sink.lock().unwrap().push_back(c);
+            Ok(())
+        });
+        Self {
+            machine: Machine::new(Ctx::with_options(stdin, out, opts), expr),
+            printed,
+            failed: false,
+        }
+    }
+
+    pub fn machine(&self) -> &Machine<'a> {
+        &self.machine
+    }
+}
+
+impl Iterator for OutputChars<'_> {
+    type Item = Result<char, Error>;
+    fn next(&mut self) -> Option<Result<char, Error>> {
+        loop {
+            if let Some(c) = self.printed.lock().unwrap().pop_front() {
+                return Some(Ok(c));
+            }
+            if self.failed {
+                return None;
+            }
+            let written = self.machine.ctx().device().output.len();
+            match self.machine.step() {
+                Ok(true) => {
+                    let device = &self.machine.ctx().device().output;
+                    if let Some(c) = decode_one(&device[written..]) {
+                        self.printed.lock().unwrap().push_back(c);
+                    }
+                }
+                Ok(false) => return None,
+                Err(e) => {
+                    self.failed = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+impl std::iter::FusedIterator for OutputChars<'_> {}
+
+/// The char that a single write to the virtual device wrote, if any. That's one
+/// byte for ASCII (or anything up to U+00FF with [`Encoding::Latin1`]), and
+/// UTF-8 otherwise.
+fn decode_one(bytes: &[u8]) -> Option<char> {
+    match bytes {
+        [] => None,
+        &[b] => Some(char::from(b)),
+        _ => std::str::from_utf8(bytes).ok()?.chars().next(),
+    }
+}
@@ -9,6 +9,14 @@ use super::*;
 pub struct ParseOptions {
     pub strict: bool,
     pub log_warnings: bool,
+    /// Have each IO builtin (`.x`, `r`, `@`, `?x` or `|`) remember where it
+    /// is in the source, so that if the program applies it under
+    /// [`IoPolicy::Deny`], [`Error::IoNotPermitted`] can say where it came
+    /// from. The builtins are [`OpFunc::Located`] rather than plain ones,
+    /// but otherwise behave the same.
+    ///
+    /// [`OpFunc::Located`]: crate::internals::OpFunc::Located
+    pub locate_io: bool,
 }
 
 pub fn parse_from_str(s: &str, o: ParseOptions) -> Result<P<Expr>, ParseError> {
@@ -36,16 +44,150 @@ pub fn parse_from_stdin(o: ParseOptions) -> Result<P<Expr>, ParseError> {
     parse_from_reader(std::io::stdin().lock(), o)
 }
 
+/// Like [`parse_from_str`], but also returns a [`SourceMap`] saying where in
+/// `s` each node of the result came from.
+pub fn parse_with_spans(s: &str, o: ParseOptions) -> Result<(P<Expr>, SourceMap), ParseError> {
+    let mut parser = Parser::new(s, None, o);
+    parser.spans = Some(HashMap::new());
+    let expr = parser.parse()?;
+    let spans = parser.spans.take().unwrap_or_default();
+    let root = expr.clone();
+    Ok((expr, SourceMap { spans, root }))
+}
+
+/// Where a node came from in the source.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Span {
+    /// The byte offset of the start of the node.
+    pub start: usize,
+    /// The byte offset just past the end of the node.
+    pub end: usize,
+    /// The line `start` is on, starting from 1.
+    pub line: usize,
+    /// The column of `start` (in chars), starting from 1.
+    pub col: usize,
+}
+
+/// The [`Span`] of every node in a parsed expression. See
+/// [`parse_with_spans`].
+///
+/// Nodes are looked up by address, so this keeps the expression it was made
+/// for alive (otherwise another node could end up at the same address).
+#[derive(Clone)]
+pub struct SourceMap {
+    spans: HashMap<usize, Span>,
+    root: P<Expr>,
+}
+
+impl SourceMap {
+    /// The span of `e`, if it's a node of the parsed expression. Nodes created
+    /// while evaluating it don't have one.
+    pub fn get(&self, e: &Expr) -> Option<Span> {
+        self.spans.get(&(e as *const Expr as usize)).copied()
+    }
+
+    /// The expression this is a map of.
+    pub fn root(&self) -> &P<Expr> {
+        &self.root
+    }
+
+    pub fn len(&self) -> usize {
+        self.spans.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spans.is_empty()
+    }
+}
+
+impl std::fmt::Debug for SourceMap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SourceMap")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+/// Parse `s`, carrying on past errors rather than stopping at the first one.
+///
+/// Characters that can't start an expression are skipped, and anything missing
+/// (operands at the end of the input, the char after a trailing `.`, unknown
+/// names, ...) is filled in with a `v`. This returns every error found, along
+/// with the expression that results. If the list is empty, the expression is
+/// exactly what [`parse_from_str`] would have returned.
+pub fn parse_recovering(s: &str, o: ParseOptions) -> (P<Expr>, Vec<ParseError>) {
+    Parser::new(s, None, o).parse_recovering()
+}
+
+/// Parse `s`, keeping its tokens for [`cst::parse_lossless`].
+pub(crate) fn parse_tokens(s: &str, o: ParseOptions) -> Result<Vec<cst::Token>, ParseError> {
+    Parser::new(s, None, o).parse_tokens()
+}
+
+/// Like [`parse_recovering`] but reading from a file. Only fails if the file
+/// can't be read.
+pub fn parse_file_recovering(
+    path: impl AsRef<std::path::Path>,
+    o: ParseOptions,
+) -> Result<(P<Expr>, Vec<ParseError>), ParseError> {
+    let path = path.as_ref();
+    let s = std::fs::read_to_string(path).map_err(|io| ParseError::io(io, Some(path)))?;
+    Ok(Parser::new(&s, Some(path), o).parse_recovering())
+}
+
+/// Parse `s`, allowing `$name` to refer to an expression in `defs`. Used by the
+/// REPL, since it's not something unlambda actually supports.
+pub(crate) fn parse_with_defs(
+    s: &str,
+    o: ParseOptions,
+    defs: &HashMap<String, P<Expr>>,
+) -> Result<P<Expr>, ParseError> {
+    let mut parser = Parser::new(s, None, o);
+    parser.defs = Some(defs);
+    parser.parse()
+}
+
 #[derive(Debug)]
 #[non_exhaustive]
 pub enum ParseErrorKind {
     UnexpectedEnd,
     UnexpectedChar(char),
+    /// A `$name` that wasn't defined. Only happens in the REPL.
+    UnknownName(String),
     Io(std::io::Error),
 }
 
-use std::io::Read;
-use ParseErrorKind::{UnexpectedChar, UnexpectedEnd};
+use std::{collections::HashMap, io::Read};
+use ParseErrorKind::{UnexpectedChar, UnexpectedEnd, UnknownName};
+
+/// What the parser was looking for when it found an error.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Expected {
+    /// The start of the program.
+    Expr,
+    /// The operator of an application (the first thing after a `` ` ``).
+    Operator,
+    /// The operand of an application (the second thing after a `` ` ``).
+    Operand,
+    /// The end of the input, since the program was already complete (only
+    /// an error with [`ParseOptions::strict`]).
+    End,
+    /// The char that goes after a `.` or `?` (which is the one held here).
+    CharAfter(char),
+}
+
+impl std::fmt::Display for Expected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Expr => "expected an expression",
+            Self::Operator => "operator expected after `",
+            Self::Operand => "operand expected after `",
+            Self::End => "expected the end of the input",
+            Self::CharAfter(c) => return write!(f, "expected a character after `{}`", c),
+        })
+    }
+}
 
 #[derive(Debug)]
 struct ParseErrorInfo {
@@ -53,6 +195,9 @@ struct ParseErrorInfo {
     line: usize,
     col: usize,
     file: Option<std::path::PathBuf>,
+    offset: Option<usize>,
+    expected: Option<Expected>,
+    open_backtick: Option<usize>,
 }
 
 #[derive(Debug)]
@@ -63,10 +208,104 @@ impl ParseError {
         self.0.file.as_deref()
     }
 
+    /// Say the error is in `file`, e.g. when you read the file yourself and
+    /// parsed its contents with [`parse_from_str`].
+    pub fn with_file(mut self, file: impl Into<std::path::PathBuf>) -> Self {
+        self.0.file = Some(file.into());
+        self
+    }
+
+    /// The line and column of the error, both starting from 1 (and counting
+    /// columns in chars). `(0, 0)` if there's no position, e.g. for IO errors.
     pub fn line_col(&self) -> (usize, usize) {
         (self.0.line, self.0.col)
     }
 
+    /// The byte offset of the error in the source.
+    pub fn offset(&self) -> Option<usize> {
+        self.0.offset
+    }
+
+    pub fn expected(&self) -> Option<Expected> {
+        self.0.expected
+    }
+
+    /// The byte offset of the innermost `` ` `` that was still missing
+    /// operands when the error happened, if any.
+    pub fn open_backtick(&self) -> Option<usize> {
+        self.0.open_backtick
+    }
+
+    /// Describe the error over several lines, quoting the offending line of
+    /// `source` (which should be what was being parsed) with a caret under
+    /// the problem, and pointing out the `` ` `` that's missing operands.
+    pub fn render(&self, source: &str) -> String {
+        let mut out = format!("error: {}\n", ErrorMessage(self));
+        let offset = match self.offset() {
+            Some(o) if o <= source.len() && source.is_char_boundary(o) => o,
+            _ => return out,
+        };
+        let (line, col) = line_col(source, offset);
+        let file = self.file().map(|p| p.display().to_string());
+        out += &format!(
+            " --> {}:{}:{}\n",
+            file.as_deref().unwrap_or("<input>"),
+            line,
+            col
+        );
+        let open = match self.expected() {
+            Some(e @ Expected::Operator) | Some(e @ Expected::Operand) => {
+                self.open_backtick().map(|o| (line_col(source, o), e))
+            }
+            _ => None,
+        };
+        let width = line.to_string().len();
+        let gutter = " ".repeat(width);
+        let text = |line: usize| source.lines().nth(line - 1).unwrap_or("");
+        let quote = |out: &mut String, line: usize| {
+            *out += &format!("{} |\n{:>w$} | {}\n", gutter, line, text(line), w = width);
+        };
+        // Blank space that lines up with `line` from column `from` to just
+        // before `to`. Tabs are kept, so it lines up however wide they are.
+        let pad = |line: usize, from: usize, to: usize| -> String {
+            let chars = text(line).chars().chain(std::iter::repeat(' '));
+            chars
+                .skip(from - 1)
+                .take(to - from)
+                .map(|c| if c == '\t' { c } else { ' ' })
+                .collect()
+        };
+        let note = self.expected().map(|e| e.to_string()).unwrap_or_default();
+        match open {
+            Some(((oline, ocol), e)) => {
+                let missing = match e {
+                    Expected::Operator => "this ` is missing its operator and operand",
+                    _ => "this ` is missing its operand",
+                };
+                if oline != line {
+                    // Show where the backtick is first, then the error.
+                    quote(&mut out, oline);
+                    out += &format!("{} | {}- {}\n", gutter, pad(oline, 1, ocol), missing);
+                    quote(&mut out, line);
+                    out += &format!("{} | {}^ {}\n", gutter, pad(line, 1, col), note);
+                } else {
+                    quote(&mut out, line);
+                    let gap = pad(line, ocol + 1, col);
+                    let pad = pad(line, 1, ocol);
+                    out += &format!("{} | {}-{}^ {}\n", gutter, pad, gap, note);
+                    out += &format!("{} | {}{}\n", gutter, pad, missing);
+                }
+            }
+            None => {
+                quote(&mut out, line);
+                let marker = format!("{} | {}^ {}", gutter, pad(line, 1, col), note);
+                out += marker.trim_end();
+                out.push('\n');
+            }
+        }
+        out
+    }
+
     pub fn is_eof(&self) -> bool {
         match self.kind() {
             UnexpectedEnd => true,
@@ -91,6 +330,9 @@ impl ParseError {
             line,
             col,
             file: file.map(ToOwned::to_owned),
+            offset: None,
+            expected: None,
+            open_backtick: None,
         }))
     }
     #[cold]
@@ -99,16 +341,25 @@ impl ParseError {
     }
 }
 
+/// Just the description of a `ParseError`, without the position.
+struct ErrorMessage<'a>(&'a ParseError);
+
+impl std::fmt::Display for ErrorMessage<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0.kind() {
+            ParseErrorKind::UnexpectedEnd => f.write_str("unexpected end of input"),
+            ParseErrorKind::UnexpectedChar(c) => write!(f, "unexpected character {:?}", c),
+            ParseErrorKind::UnknownName(n) => write!(f, "unknown name `${}`", n),
+            ParseErrorKind::Io(io) => io.fmt(f),
+        }
+    }
+}
+
 impl std::fmt::Display for ParseError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self.kind() {
-            ParseErrorKind::UnexpectedEnd => f.write_str("unexpected end of input")?,
-            ParseErrorKind::UnexpectedChar(c) => write!(f, "unexpected character {:?}", c)?,
-            ParseErrorKind::Io(io) => io.fmt(f)?,
-        };
+        ErrorMessage(self).fmt(f)?;
         match self.line_col() {
             (0, 0) => {}
-            (0, c) => write!(f, " at column {}", c)?,
             (l, c) => write!(f, " at line {}, column {}", l, c)?,
         }
         if let Some(p) = self.file() {
@@ -133,6 +384,15 @@ struct Parser<'a> {
     src: Option<&'a std::path::Path>,
     pos: usize,
     opts: ParseOptions,
+    defs: Option<&'a HashMap<String, P<Expr>>>,
+    /// Only recorded if we're asked for them.
+    spans: Option<HashMap<usize, Span>>,
+    /// The byte offset of the start of each line, once we need it.
+    line_starts: Vec<usize>,
+    /// Where errors go if we're recovering from them.
+    errors: Option<Vec<ParseError>>,
+    /// The tokens so far, if we're building a [`cst::SyntaxTree`].
+    tokens: Option<Vec<cst::Token>>,
 }
 
 impl<'a> Parser<'a> {
@@ -142,95 +402,234 @@ impl<'a> Parser<'a> {
             src,
             pos: 0,
             opts,
+            defs: None,
+            spans: None,
+            line_starts: vec![],
+            errors: None,
+            tokens: None,
         }
     }
 
-    fn line_col(&self) -> (usize, usize) {
-        self.line_col_at(self.pos)
+    /// Hold on to `e` and keep going if we're recovering from errors,
+    /// otherwise give up.
+    fn recover(&mut self, e: ParseError) -> Result<(), ParseError> {
+        match &mut self.errors {
+            Some(errors) => {
+                errors.push(e);
+                Ok(())
+            }
+            None => Err(e),
+        }
     }
 
-    fn line_col_at(&self, p: usize) -> (usize, usize) {
-        let s = &self.input[..p];
-        let l = s.lines().count();
-        let r_start = self.input.rfind('\n').map(|v| v + 1).unwrap_or(0);
-        let row = &self.input[r_start..];
-        // chars isn't perfect but it's probably good enough.
-        let chars = row.chars().count().saturating_sub(1);
-        (l, chars)
+    /// Note where `e` came from, if anyone wants to know. With
+    /// [`ParseOptions::locate_io`], an IO builtin is replaced with one that
+    /// knows its span.
+    fn record_span(&mut self, e: &mut P<Expr>, start: usize) {
+        let locate = self.opts.locate_io && matches!(&**e, Expr::Func(f) if f.is_io());
+        if self.spans.is_none() && !locate {
+            return;
+        }
+        if self.line_starts.is_empty() {
+            self.line_starts = std::iter::once(0)
+                .chain(self.input.match_indices('\n').map(|(i, _)| i + 1))
+                .collect();
+        }
+        let line = self.line_starts.partition_point(|&s| s <= start);
+        let line_start = self.line_starts[line - 1];
+        let span = Span {
+            start,
+            end: self.pos,
+            line,
+            col: self.input[line_start..start].chars().count() + 1,
+        };
+        if let (true, Expr::Func(f)) = (locate, &**e) {
+            *e = p(Expr::Func(Func::located(f.clone(), span)));
+        }
+        if let Some(spans) = &mut self.spans {
+            spans.insert(&**e as *const Expr as usize, span);
+        }
+    }
+
+    /// Note the token from `start` to where we are now, and the trivia from
+    /// `before` up to it, if we're building a syntax tree. `e` is what it
+    /// parsed to, or `None` for a backtick.
+    fn record_token(&mut self, before: usize, start: usize, e: Option<&Expr>) {
+        if let Some(tokens) = &mut self.tokens {
+            let func = e.map(|e| match e {
+                Expr::Func(f) => f.clone(),
+                Expr::App(..) => unreachable!("syntax trees don't have `$name`s"),
+            });
+            let trivia = &self.input[before..start];
+            tokens.push(cst::Token::new(trivia, &self.input[start..self.pos], func));
+        }
     }
 
     fn char_at(&self, p: usize) -> Option<char> {
         self.input.get(p..).and_then(|s| s.chars().next())
     }
 
+    /// The first char of the next token that isn't a comment or whitespace.
+    /// Only that char is consumed, so e.g. for a `.x`, `raw_next` gets the
+    /// `x`.
     fn try_next(&mut self) -> Option<char> {
-        let mut c;
-        loop {
-            c = self.char_at(self.pos)?;
-            self.pos += c.len_utf8();
-            if c == '#' {
-                while c != '\n' {
-                    c = self.char_at(self.pos)?;
-                    self.pos += c.len_utf8();
-                }
-            }
-            if !c.is_whitespace() {
-                break;
-            }
-        }
+        let t = lex::tokenize_from(self.input, self.pos).find(|t| !t.kind.is_trivia())?;
+        let c = self.char_at(t.range.start)?;
+        self.pos = t.range.start + c.len_utf8();
         Some(c)
     }
 
+    /// An error at byte `offset`.
     #[cold]
-    fn error(&self, kind: ParseErrorKind) -> ParseError {
-        let (line, col) = self.line_col();
-        ParseError::new(kind, line, col, self.src)
+    fn error(&self, kind: ParseErrorKind, offset: usize) -> ParseError {
+        let (line, col) = line_col(self.input, offset);
+        let mut e = ParseError::new(kind, line, col, self.src);
+        e.0.offset = Some(offset);
+        e
+    }
+
+    /// Note what we were in the middle of when `e` happened.
+    #[cold]
+    fn in_context(e: ParseError, stack: &[(usize, Option<P<Expr>>)]) -> ParseError {
+        let mut e = e;
+        e.0.expected.get_or_insert(match stack.last() {
+            None => Expected::Expr,
+            Some((_, None)) => Expected::Operator,
+            Some((_, Some(_))) => Expected::Operand,
+        });
+        e.0.open_backtick = stack.last().map(|&(start, _)| start);
+        e
     }
 
     fn next_c(&mut self) -> Result<char, ParseError> {
-        self.try_next().ok_or_else(|| self.error(UnexpectedEnd))
+        self.try_next()
+            .ok_or_else(|| self.error(UnexpectedEnd, self.pos))
     }
 
+    /// Note: This uses an explicit stack rather than recursion, since
+    /// machine-generated programs can easily nest deeply enough to overflow
+    /// the real one.
     fn parse_expr(&mut self) -> Result<P<Expr>, ParseError> {
-        let ch = self.next_c()?;
+        // One entry per backtick we're inside, holding where it is, and its
+        // operator once we have it.
+        let mut stack: Vec<(usize, Option<P<Expr>>)> = vec![];
+        // When recovering, set once we've run out of input, after which
+        // everything still missing is a placeholder.
+        let mut at_end = false;
+        loop {
+            let before = self.pos;
+            let ch = if at_end {
+                None
+            } else {
+                match self.next_c() {
+                    Ok(ch) => Some(ch),
+                    Err(e) => {
+                        self.recover(Self::in_context(e, &stack))?;
+                        at_end = true;
+                        None
+                    }
+                }
+            };
+            let (start, mut e) = match ch {
+                None => (self.pos, placeholder()),
+                Some('`') => {
+                    self.record_token(before, self.pos - 1, None);
+                    stack.push((self.pos - 1, None));
+                    continue;
+                }
+                Some(ch) => {
+                    let start = self.pos - ch.len_utf8();
+                    match self.parse_leaf(ch) {
+                        Ok(e) => {
+                            self.record_token(before, start, Some(&e));
+                            (start, e)
+                        }
+                        Err(err) => {
+                            // Skip over bad chars entirely, but something
+                            // like an unknown name still takes up a slot.
+                            let skip = matches!(err.kind(), UnexpectedChar(_));
+                            at_end = err.is_eof();
+                            self.recover(Self::in_context(err, &stack))?;
+                            if skip {
+                                continue;
+                            }
+                            (start, placeholder())
+                        }
+                    }
+                }
+            };
+            self.record_span(&mut e, start);
+            // Finish off every application that this completes.
+            loop {
+                match stack.pop() {
+                    None => return Ok(e),
+                    Some((start, None)) => {
+                        stack.push((start, Some(e)));
+                        break;
+                    }
+                    Some((start, Some(operator))) => {
+                        e = p(Expr::App(operator, e));
+                        self.record_span(&mut e, start);
+                    }
+                }
+            }
+        }
+    }
+
+    fn parse_leaf(&mut self, ch: char) -> Result<P<Expr>, ParseError> {
+        if let Some(f) = builtin(ch) {
+            return Ok(p(Expr::Func(f)));
+        }
         match ch {
-            '`' => {
-                let operator = self.parse_expr()?;
-                let operand = self.parse_expr()?;
-                Ok(p(Expr::App(operator, operand)))
+            '.' => Ok(p(Expr::Func(Func::Dot(self.raw_next(ch)?)))),
+            '?' => Ok(p(Expr::Func(Func::Q(self.raw_next(ch)?)))),
+            '$' => match self.defs {
+                Some(defs) => self.parse_name(defs),
+                None => Err(self.error(UnexpectedChar('$'), self.pos - 1)),
+            },
+            other => Err(self.error(UnexpectedChar(other), self.pos - other.len_utf8())),
+        }
+    }
+
+    fn parse_name(&mut self, defs: &HashMap<String, P<Expr>>) -> Result<P<Expr>, ParseError> {
+        let start = self.pos;
+        while let Some(c) = self.char_at(self.pos) {
+            if !is_name_char(c) {
+                break;
             }
-            'i' | 'I' => Ok(p(Expr::Func(Func::I))),
-            'k' | 'K' => Ok(p(Expr::Func(Func::K))),
-            's' | 'S' => Ok(p(Expr::Func(Func::S))),
-            'd' | 'D' => Ok(p(Expr::Func(Func::D))),
-            'e' | 'E' => Ok(p(Expr::Func(Func::E))),
-            'c' | 'C' => Ok(p(Expr::Func(Func::C))),
-            'v' | 'V' => Ok(p(Expr::Func(Func::V))),
-            'r' | 'R' => Ok(p(Expr::Func(Func::Dot('\n')))),
-            '@' => Ok(p(Expr::Func(Func::At))),
-            '|' => Ok(p(Expr::Func(Func::Pipe))),
-            '.' => Ok(p(Expr::Func(Func::Dot(self.raw_next()?)))),
-            '?' => Ok(p(Expr::Func(Func::Q(self.raw_next()?)))),
-            other => Err(self.error(UnexpectedChar(other))),
-        }
-    }
-
-    fn raw_next(&mut self) -> Result<char, ParseError> {
+            self.pos += c.len_utf8();
+        }
+        let name = &self.input[start..self.pos];
+        if name.is_empty() {
+            return Err(self.error(UnexpectedChar('$'), start - 1));
+        }
+        defs.get(name)
+            .cloned()
+            .ok_or_else(|| self.error(UnknownName(name.into()), start - 1))
+    }
+
+    /// The char after a `.` or `?` (which is `after`), whatever it is.
+    fn raw_next(&mut self, after: char) -> Result<char, ParseError> {
         self.char_at(self.pos)
-            .ok_or_else(|| self.error(UnexpectedEnd))
-            .map(|c| {
+            .ok_or_else(|| {
+                let mut e = self.error(UnexpectedEnd, self.pos);
+                e.0.expected = Some(Expected::CharAfter(after));
+                e
+            })
+            .inspect(|c| {
                 self.pos += c.len_utf8();
-                c
             })
     }
 
-    fn parse(mut self) -> Result<P<Expr>, ParseError> {
+    fn parse(&mut self) -> Result<P<Expr>, ParseError> {
         let e = self.parse_expr()?;
         if let Some(v) = self.try_next() {
+            let mut err = self.error(UnexpectedChar(v), self.pos - v.len_utf8());
+            err.0.expected = Some(Expected::End);
             if self.opts.strict {
-                return Err(self.error(UnexpectedChar(v)));
+                self.recover(err)?;
             } else if self.opts.log_warnings {
-                let _lc = self.error(UnexpectedChar(v)).to_string();
+                let _lc = err.to_string();
                 #[cfg(feature = "log")]
                 {
                     log::warn!("Ignoring trailing garbage after expression: {}", _lc);
@@ -239,4 +638,51 @@ impl<'a> Parser<'a> {
         }
         Ok(e)
     }
+
+    fn parse_recovering(&mut self) -> (P<Expr>, Vec<ParseError>) {
+        self.errors = Some(vec![]);
+        let e = self.parse().expect("errors are all recovered from");
+        (e, self.errors.take().unwrap_or_default())
+    }
+
+    fn parse_tokens(&mut self) -> Result<Vec<cst::Token>, ParseError> {
+        self.tokens = Some(vec![]);
+        self.parse()?;
+        Ok(self.tokens.take().unwrap_or_default())
+    }
+}
+
+/// The builtin that `c` stands for, for everything but `.x` and `?x`.
+pub(crate) fn builtin(c: char) -> Option<Func> {
+    Some(match c {
+        'i' | 'I' => Func::I,
+        'k' | 'K' => Func::K,
+        's' | 'S' => Func::S,
+        'd' | 'D' => Func::D,
+        'e' | 'E' => Func::E,
+        'c' | 'C' => Func::C,
+        'v' | 'V' => Func::V,
+        'r' | 'R' => Func::Dot('\n'),
+        '@' => Func::At,
+        '|' => Func::Pipe,
+        _ => return None,
+    })
+}
+
+/// What goes wherever [`parse_recovering`] couldn't find what it needed.
+fn placeholder() -> P<Expr> {
+    p(Expr::Func(Func::V))
+}
+
+/// The line and column of byte `offset` in `s`, both starting from 1.
+fn line_col(s: &str, offset: usize) -> (usize, usize) {
+    let before = &s[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let line = before.matches('\n').count() + 1;
+    // chars isn't perfect but it's probably good enough.
+    (line, before[line_start..].chars().count() + 1)
+}
+
+pub(crate) fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
 }
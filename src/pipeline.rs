@@ -0,0 +1,129 @@
+//! Connecting programs together like a shell pipeline (`a | b | c`), where
+//! what each program prints is what the next one reads.
+
+use super::*;
+use std::{
+    collections::VecDeque,
+    io::Read,
+    sync::{Arc, Mutex},
+};
+
+/// How many steps a stage runs before we check whether the stages after it
+/// have anything to do.
+const SLICE: u64 = 1024;
+
+/// Bytes written by one stage that the next hasn't read yet.
+#[derive(Clone, Default)]
+struct Pipe(Arc<Mutex<VecDeque<u8>>>);
+
+impl std::io::Write for Pipe {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Several programs, each reading the output of the one before it.
+///
+/// Everything runs on the current thread: the stages take turns, and a stage
+/// only runs when the ones after it are waiting for input (or done), so the
+/// output of one stage never piles up much before the next stage reads it.
+/// Once the last stage finishes, the pipeline is done, even if earlier stages
+/// aren't (like a shell pipeline where `head` exits early).
+pub struct Pipeline<'a> {
+    stages: Vec<Machine<'a>>,
+    /// `pipes[i]` connects `stages[i]` to `stages[i + 1]`.
+    pipes: Vec<Pipe>,
+}
+
+impl<'a> Pipeline<'a> {
+    /// Set up `exprs` to run as a pipeline. The first reads from `stdin`, the
+    /// last writes to `stdout`, and each runs with `opts`.
+    ///
+    /// Panics if `exprs` is empty.
+    pub fn new<O>(
+        stdin: impl Read + Send + 'a,
+        stdout: O,
+        exprs: impl IntoIterator<Item = P<Expr>>,
+        opts: EvalOptions,
+    ) -> Self
+    where
+        O: Into<io::Output<'a>>,
+    {
+        let exprs = exprs.into_iter().collect::<Vec<_>>();
+        assert!(!exprs.is_empty(), "a pipeline needs at least one program");
+        let last = exprs.len() - 1;
+        let pipes = vec![Pipe::default(); last];
+        let mut stdin: Option<Box<dyn Read + Send + 'a>> = Some(Box::new(stdin));
+        let mut stdout = Some(stdout.into());
+        let stages = exprs
+            .into_iter()
+            .enumerate()
+            .map(|(i, e)| {
+                // Stages after the first get their input from `feed`.
+                let input = stdin.take().unwrap_or_else(|| Box::new(std::io::empty()));
+                let output = match pipes.get(i) {
+                    Some(pipe) => io::Output::writer(pipe.clone()),
+                    None => stdout.take().expect("only the last stage has no pipe"),
+                };
+                Machine::new(Ctx::with_options(input, output, opts.clone()), e)
+            })
+            .collect();
+        Self { stages, pipes }
+    }
+
+    pub fn stages(&self) -> &[Machine<'a>] {
+        &self.stages
+    }
+
+    /// Run the pipeline until the last stage finishes, and return its result.
+    pub fn run(&mut self) -> Result<Func, Error> {
+        let last = self.stages.len() - 1;
+        loop {
+            if let Some(v) = self.stages[last].result() {
+                return Ok(v.clone());
+            }
+            // Run the last stage that isn't waiting for input. There always
+            // is one, since the first stage never waits.
+            for i in (0..=last).rev() {
+                if !self.stages[i].is_finished() && self.run_stage(i)? {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Run stage `i` for a while. Returns false if it's waiting for input.
+    fn run_stage(&mut self, i: usize) -> Result<bool, Error> {
+        let mut steps = 0;
+        while steps < SLICE && !self.stages[i].is_finished() {
+            let stage = &self.stages[i];
+            if i > 0 && stage.ctx().needs_input(stage.task()) && !self.feed(i)? {
+                break;
+            }
+            self.stages[i].step()?;
+            steps += 1;
+        }
+        Ok(steps > 0)
+    }
+
+    /// Move bytes from the pipe into stage `i` until it has a character to
+    /// read. Returns false if the pipe runs dry first.
+    fn feed(&mut self, i: usize) -> Result<bool, Error> {
+        let upstream_done = self.stages[i - 1].is_finished();
+        let mut pipe = self.pipes[i - 1].0.lock().unwrap();
+        let ctx = self.stages[i].ctx_mut();
+        while !ctx.input_ready()? {
+            match pipe.pop_front() {
+                Some(b) => ctx.lookahead.push(Some(b)),
+                None if upstream_done => ctx.lookahead.push(None),
+                None => return Ok(false),
+            }
+        }
+        Ok(true)
+    }
+}
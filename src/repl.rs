@@ -0,0 +1,118 @@
+//! A read-eval-print loop. The binary drives this with `--repl`, but it's
+//! usable on its own: feed it lines, and it tells you what happened.
+//!
+//! Every line is evaluated against the same [`Ctx`], so the current character
+//! and the position in the input carry over from one line to the next.
+//!
+//! Besides plain unlambda, a line of the form `$name = <expr>` binds `name` to
+//! the (unevaluated) expression, after which `$name` can be used anywhere an
+//! expression can be.
+
+use super::*;
+use std::collections::HashMap;
+
+/// What happened as a result of [`Repl::feed_line`].
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Reply {
+    /// Nothing but whitespace and comments.
+    Empty,
+    /// The input so far is an incomplete expression, more lines are needed.
+    Incomplete,
+    /// A name was (re)defined.
+    Defined(String),
+    /// An expression was evaluated, producing this value.
+    Value(Func),
+}
+
+pub struct Repl<'a> {
+    ctx: Ctx<'a>,
+    defs: HashMap<String, P<Expr>>,
+    pending: String,
+}
+
+impl<'a> Repl<'a> {
+    pub fn new(ctx: Ctx<'a>) -> Self {
+        Self {
+            ctx,
+            defs: HashMap::new(),
+            pending: String::new(),
+        }
+    }
+
+    pub fn ctx_mut(&mut self) -> &mut Ctx<'a> {
+        &mut self.ctx
+    }
+
+    /// Look up the expression bound to `name` (without the `$`).
+    pub fn get(&self, name: &str) -> Option<&P<Expr>> {
+        self.defs.get(name)
+    }
+
+    /// True if we're in the middle of a multi-line expression.
+    pub fn is_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Throw away any partial input from previous lines.
+    pub fn clear_pending(&mut self) {
+        self.pending.clear();
+    }
+
+    /// Feed the REPL a line of input. If the line leaves backticks without
+    /// enough operands, it's remembered and [`Reply::Incomplete`] is returned,
+    /// and the next line continues it.
+    pub fn feed_line(&mut self, line: &str) -> Result<Reply, Error> {
+        self.pending.push_str(line);
+        if !line.ends_with('\n') {
+            self.pending.push('\n');
+        }
+        let (name, src) = split_definition(&self.pending);
+        if name.is_none() && is_blank(src) {
+            self.pending.clear();
+            return Ok(Reply::Empty);
+        }
+        let opts = ParseOptions {
+            strict: true,
+            log_warnings: false,
+            locate_io: false,
+        };
+        let expr = match parse_with_defs(src, opts, &self.defs) {
+            Ok(expr) => expr,
+            Err(e) if e.is_eof() => return Ok(Reply::Incomplete),
+            Err(e) => {
+                self.pending.clear();
+                return Err(e.into());
+            }
+        };
+        let name = name.map(ToOwned::to_owned);
+        self.pending.clear();
+        if let Some(name) = name {
+            self.defs.insert(name.clone(), expr);
+            Ok(Reply::Defined(name))
+        } else {
+            self.ctx.evaluate(expr).map(Reply::Value)
+        }
+    }
+}
+
+/// Splits `$name = expr` into `(Some(name), expr)`. Anything else is just an
+/// expression.
+fn split_definition(s: &str) -> (Option<&str>, &str) {
+    let trimmed = s.trim_start();
+    if let Some(rest) = trimmed.strip_prefix('$') {
+        let len = rest.find(|c| !is_name_char(c)).unwrap_or(rest.len());
+        let (name, rest) = rest.split_at(len);
+        if let Some(body) = rest.trim_start().strip_prefix('=') {
+            if !name.is_empty() {
+                return (Some(name), body);
+            }
+        }
+    }
+    (None, s)
+}
+
+fn is_blank(s: &str) -> bool {
+    s.lines()
+        .all(|l| l.split('#').next().unwrap_or("").trim().is_empty())
+}
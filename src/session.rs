@@ -0,0 +1,70 @@
+//! Running several programs one after another, as stages of one bigger
+//! program.
+
+use super::*;
+
+/// Runs programs in order on the same [`Ctx`], so they share its input (each
+/// one picks up reading where the last left off), its output, and its
+/// options.
+///
+/// By default the current character (what `?x` and `|` look at) carries over
+/// from one program to the next too. Use [`Session::set_reset_last_char`] to
+/// have each program start without one, as it would on its own.
+pub struct Session<'a> {
+    ctx: Ctx<'a>,
+    reset_last_char: bool,
+}
+
+impl<'a> Session<'a> {
+    pub fn new(ctx: Ctx<'a>) -> Self {
+        Self {
+            ctx,
+            reset_last_char: false,
+        }
+    }
+
+    /// Whether to forget the current character before running each program.
+    pub fn set_reset_last_char(&mut self, reset: bool) {
+        self.reset_last_char = reset;
+    }
+
+    /// The current character, as left by the last program that read input.
+    pub fn last_char(&self) -> Option<char> {
+        self.ctx.last_char()
+    }
+
+    /// Set the current character for the next program. This is how you reset
+    /// it between two particular programs.
+    pub fn set_last_char(&mut self, c: Option<char>) {
+        self.ctx.set_last_char(c);
+    }
+
+    /// Run `expr`, returning the value it finished with.
+    pub fn run(&mut self, expr: P<Expr>) -> Result<Func, Error> {
+        if self.reset_last_char {
+            self.ctx.set_last_char(None);
+        }
+        self.ctx.evaluate(expr)
+    }
+
+    /// Run each of `exprs` in order, stopping at the first error. Returns the
+    /// values they finished with.
+    pub fn run_all(
+        &mut self,
+        exprs: impl IntoIterator<Item = P<Expr>>,
+    ) -> Result<Vec<Func>, Error> {
+        exprs.into_iter().map(|e| self.run(e)).collect()
+    }
+
+    pub fn ctx(&self) -> &Ctx<'a> {
+        &self.ctx
+    }
+
+    pub fn ctx_mut(&mut self) -> &mut Ctx<'a> {
+        &mut self.ctx
+    }
+
+    pub fn into_ctx(self) -> Ctx<'a> {
+        self.ctx
+    }
+}
@@ -0,0 +1,203 @@
+//! Transcripts of a program's IO, for reproducing what happened in an
+//! interactive session.
+//!
+//! Call [`Ctx::record`] before running a program, and [`Ctx::take_transcript`]
+//! afterwards to get every character it read and wrote, along with the step it
+//! happened on. Later, [`Ctx::replay`] feeds the recorded input back to the
+//! program and checks that it prints the same things, stopping with
+//! [`Error::Diverged`] the first time it doesn't.
+//!
+//! Transcripts convert to and from a simple text format (with `Display` and
+//! `FromStr`), one event per line:
+//!
+//! ```text
+//! # unlambda transcript
+//! 12 in 'h'
+//! 30 out 'h'
+//! 41 in '\n'
+//! 57 in eof
+//! ```
+//!
+//! Characters are written as Rust `char` literals. Blank lines and lines
+//! starting with `#` are ignored.
+
+use super::*;
+
+/// Something a program did.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// Read a character with `@`, or hit EOF (`None`).
+    Read(Option<char>),
+    /// Printed a character.
+    Write(char),
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Transcript {
+    events: Vec<(u64, Event)>,
+}
+
+impl Transcript {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The events in the transcript, along with the step they happened on.
+    pub fn events(&self) -> &[(u64, Event)] {
+        &self.events
+    }
+
+    pub fn push(&mut self, step: u64, event: Event) {
+        self.events.push((step, event));
+    }
+}
+
+impl std::fmt::Display for Transcript {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "# unlambda transcript")?;
+        for (step, e) in &self.events {
+            match e {
+                Event::Read(Some(c)) => writeln!(f, "{} in {:?}", step, c)?,
+                Event::Read(None) => writeln!(f, "{} in eof", step)?,
+                Event::Write(c) => writeln!(f, "{} out {:?}", step, c)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for Transcript {
+    type Err = TranscriptError;
+    fn from_str(s: &str) -> Result<Self, TranscriptError> {
+        let mut t = Transcript::new();
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let bad = || TranscriptError(i + 1);
+            let mut parts = line.splitn(3, ' ');
+            let step = parts.next().and_then(|s| s.parse().ok()).ok_or_else(bad)?;
+            let event = match (parts.next(), parts.next()) {
+                (Some("in"), Some("eof")) => Event::Read(None),
+                (Some("in"), Some(c)) => Event::Read(Some(parse_char(c).ok_or_else(bad)?)),
+                (Some("out"), Some(c)) => Event::Write(parse_char(c).ok_or_else(bad)?),
+                _ => return Err(bad()),
+            };
+            t.push(step, event);
+        }
+        Ok(t)
+    }
+}
+
+/// Parses a char literal, as written by `{:?}`.
+fn parse_char(s: &str) -> Option<char> {
+    let s = s.strip_prefix('\'')?.strip_suffix('\'')?;
+    let mut chars = s.chars();
+    let c = match chars.next()? {
+        '\\' => match chars.next()? {
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            '0' => '\0',
+            c @ '\\' | c @ '\'' | c @ '"' => c,
+            'u' => {
+                let hex = chars.as_str().strip_prefix('{')?.strip_suffix('}')?;
+                chars = "".chars();
+                std::char::from_u32(u32::from_str_radix(hex, 16).ok()?)?
+            }
+            _ => return None,
+        },
+        c => c,
+    };
+    match chars.next() {
+        None => Some(c),
+        Some(_) => None,
+    }
+}
+
+/// A transcript that failed to parse. Contains the (1-based) line it failed
+/// on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TranscriptError(pub usize);
+
+impl std::fmt::Display for TranscriptError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "malformed transcript at line {}", self.0)
+    }
+}
+
+impl std::error::Error for TranscriptError {}
+
+/// What a program did that didn't match the transcript being replayed. See
+/// [`Divergence`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Read,
+    Write(char),
+    /// Finished (see [`Ctx::finish_replay`]).
+    End,
+}
+
+/// Where a replayed program stopped matching its transcript.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Divergence {
+    /// The step the program was on.
+    pub step: u64,
+    /// The next event in the transcript, and the step it was recorded on, or
+    /// `None` if the transcript had no more events.
+    pub expected: Option<(u64, Event)>,
+    /// What the program did instead.
+    pub found: Action,
+}
+
+impl std::fmt::Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "transcript diverged at step {}: expected ", self.step)?;
+        match self.expected {
+            Some((s, Event::Read(_))) => write!(f, "a read (at step {})", s)?,
+            Some((s, Event::Write(c))) => write!(f, "output {:?} (at step {})", c, s)?,
+            None => f.write_str("the end of the transcript")?,
+        }
+        match self.found {
+            Action::Read => f.write_str(", but the program read input"),
+            Action::Write(c) => write!(f, ", but the program printed {:?}", c),
+            Action::End => f.write_str(", but the program finished"),
+        }
+    }
+}
+
+/// The transcript a [`Ctx`] is recording or replaying.
+#[derive(Debug)]
+pub(crate) enum Recording {
+    Record(Transcript),
+    /// The transcript, and the index of the next event in it.
+    Replay(Transcript, usize),
+}
+
+impl Recording {
+    /// Check that `found` is the next thing in the transcript we're replaying,
+    /// returning the event if it is.
+    pub(crate) fn expect(&mut self, step: u64, found: Action) -> Result<Event, Error> {
+        let (t, pos) = match self {
+            Self::Replay(t, pos) => (t, pos),
+            Self::Record(_) => unreachable!("only called when replaying"),
+        };
+        let expected = t.events.get(*pos).copied();
+        match (expected, found) {
+            (Some((_, e @ Event::Read(_))), Action::Read) => {
+                *pos += 1;
+                Ok(e)
+            }
+            (Some((_, e @ Event::Write(c))), Action::Write(d)) if c == d => {
+                *pos += 1;
+                Ok(e)
+            }
+            _ => Err(Error::Diverged(Divergence {
+                step,
+                expected,
+                found,
+            })),
+        }
+    }
+}
@@ -1,3 +1,11 @@
+use std::{
+    cell::RefCell,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
 #[cfg(feature = "arc")]
 type InnerP<T> = std::sync::Arc<T>;
 #[cfg(not(feature = "arc"))]
@@ -9,22 +17,91 @@ type InnerP<T> = std::rc::Rc<T>;
 /// wrapper.
 ///
 /// Don't ask me about the 1-char name, I wrote this like a year ago.
-#[derive(Clone, Debug, PartialEq, PartialOrd)]
 #[repr(transparent)]
-pub struct P<T>(InnerP<T>);
+pub struct P<T>(InnerP<Tracked<T>>);
+
+/// The value behind a [`P`], along with (possibly) a guard that keeps it
+/// counted in a [`LiveObjects`].
+struct Tracked<T> {
+    value: T,
+    _live: Option<LiveGuard>,
+}
 
 impl<T> std::ops::Deref for P<T> {
     type Target = T;
     #[inline]
     fn deref(&self) -> &T {
-        &*self.0
+        &self.0.value
+    }
+}
+
+impl<T> Clone for P<T> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for P<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<T: PartialEq> PartialEq for P<T> {
+    #[inline]
+    fn eq(&self, o: &Self) -> bool {
+        **self == **o
+    }
+}
+
+impl<T: PartialOrd> PartialOrd for P<T> {
+    #[inline]
+    fn partial_cmp(&self, o: &Self) -> Option<std::cmp::Ordering> {
+        (**self).partial_cmp(&**o)
+    }
+}
+
+impl<T: std::fmt::Display> std::fmt::Display for P<T> {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        (**self).fmt(f)
     }
 }
 
 impl<T> P<T> {
     #[inline]
     pub fn new(v: T) -> Self {
-        Self(InnerP::new(v))
+        // Almost nothing sets an object limit, so don't make everyone else
+        // pay for the thread-local lookup.
+        let live = if SCOPES.load(Ordering::Relaxed) == 0 {
+            None
+        } else {
+            TRACKING.with(|t| t.borrow().as_ref().map(LiveGuard::new))
+        };
+        Self(InnerP::new(Tracked {
+            value: v,
+            _live: live,
+        }))
+    }
+
+    /// Like `P::new`, but never counted in a [`LiveObjects`].
+    pub(crate) fn untracked(v: T) -> Self {
+        Self(InnerP::new(Tracked {
+            value: v,
+            _live: None,
+        }))
+    }
+
+    #[inline]
+    pub(crate) fn is_unique(this: &Self) -> bool {
+        InnerP::strong_count(&this.0) == 1
+    }
+
+    /// Mutable access to the value, if nothing else has a reference to it.
+    #[inline]
+    pub(crate) fn get_mut(this: &mut Self) -> Option<&mut T> {
+        InnerP::get_mut(&mut this.0).map(|t| &mut t.value)
     }
 }
 
@@ -32,3 +109,54 @@ impl<T> P<T> {
 pub(crate) fn p<T>(v: T) -> P<T> {
     P::new(v)
 }
+
+/// How many [`TrackingScope`]s exist, on any thread.
+static SCOPES: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// The counter that `P::new` should add to, if any.
+    static TRACKING: RefCell<Option<LiveObjects>> = const { RefCell::new(None) };
+}
+
+/// A count of the `P`s that were allocated while it was being tracked (see
+/// [`LiveObjects::track`]) and haven't been freed yet.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct LiveObjects(Arc<AtomicUsize>);
+
+impl LiveObjects {
+    pub(crate) fn count(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Count allocations made on this thread until the returned guard is
+    /// dropped.
+    pub(crate) fn track(&self) -> TrackingScope {
+        let prev = TRACKING.with(|t| t.borrow_mut().replace(self.clone()));
+        SCOPES.fetch_add(1, Ordering::Relaxed);
+        TrackingScope(prev)
+    }
+}
+
+pub(crate) struct TrackingScope(Option<LiveObjects>);
+
+impl Drop for TrackingScope {
+    fn drop(&mut self) {
+        TRACKING.with(|t| *t.borrow_mut() = self.0.take());
+        SCOPES.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+struct LiveGuard(LiveObjects);
+
+impl LiveGuard {
+    fn new(l: &LiveObjects) -> Self {
+        l.0.fetch_add(1, Ordering::Relaxed);
+        Self(l.clone())
+    }
+}
+
+impl Drop for LiveGuard {
+    fn drop(&mut self) {
+        (self.0).0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
@@ -228,3 +228,1028 @@ fn unl_test_g9() {
     assert_evals_to!("`r```s``s`k`s`kd``ssi`k.*i", "\n");
     assert_evals_to!("`r```s``s`k`s`kds`k.*i", "\n");
 }
+
+#[test]
+fn cli() {
+    use std::{io::Write, process::Command};
+    let run = |args: &[&str]| {
+        let out = Command::new(env!("CARGO_BIN_EXE_unlambda"))
+            .args(args)
+            .output()
+            .unwrap();
+        (out.status.code(), String::from_utf8(out.stdout).unwrap())
+    };
+    assert_eq!(
+        run(&["-e", "``cd``d`@|`cd", "-s", "meow"]),
+        (Some(0), "meow".to_string()),
+    );
+    assert_eq!(
+        run(&["-e", "``cd``d`@|`cd", "-i", "/nonexistent"]).0,
+        Some(74)
+    );
+    assert_eq!(run(&["-e", "``cd"]).0, Some(65));
+    assert_eq!(run(&["-e", "`.ai`", "--strict"]).0, Some(65));
+    assert_eq!(run(&["-e", "`.ai`"]), (Some(0), "a".to_string()));
+    assert_eq!(run(&["--bogus"]).0, Some(64));
+
+    // Programs that aren't UTF-8 are bad data, wherever they come from.
+    let path = std::env::temp_dir().join(format!("unlambda-cli-{}.unl", std::process::id()));
+    std::fs::write(&path, b"`.a\xffi").unwrap();
+    assert_eq!(run(&[path.to_str().unwrap()]).0, Some(65));
+    let mut stdin = Command::new(env!("CARGO_BIN_EXE_unlambda"))
+        .stdin(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+    stdin.stdin.take().unwrap().write_all(b"`.a\xffi").unwrap();
+    assert_eq!(stdin.wait().unwrap().code(), Some(65));
+    // Errors in a file say which file.
+    std::fs::write(&path, b"``cd").unwrap();
+    let out = Command::new(env!("CARGO_BIN_EXE_unlambda"))
+        .arg(&path)
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(out.status.code(), Some(65));
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(
+        stderr.contains(&format!(" --> {}:1:5\n", path.display())),
+        "{}",
+        stderr
+    );
+
+    // The REPL's expressions come from stdin, so programs can't read it.
+    let mut repl = Command::new(env!("CARGO_BIN_EXE_unlambda"))
+        .arg("--repl")
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    repl.stdin
+        .take()
+        .unwrap()
+        .write_all(b"`@i\n`.ai\n")
+        .unwrap();
+    let out = repl.wait_with_output().unwrap();
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "> > ai\n> \n");
+    assert!(String::from_utf8(out.stderr).unwrap().contains("no input"));
+}
+
+#[test]
+fn repl() {
+    use unlambda::{internals::Func, io::Ctx, repl::*};
+    let mut repl = Repl::new(Ctx::new(std::io::Cursor::new("xy"), Vec::new()));
+    let mut value = |line: &str| match repl.feed_line(line).unwrap() {
+        Reply::Value(v) => v.to_string(),
+        other => panic!("expected a value from {:?}, got {:?}", line, other),
+    };
+    assert_eq!(value("`.ai"), "i");
+    assert_eq!(value("``s`kk.x"), "``s`kk.x");
+    assert_eq!(value("`e.z"), ".z");
+
+    let mut repl = Repl::new(Ctx::new(std::io::Cursor::new("xy"), Vec::new()));
+    assert_eq!(repl.feed_line("# nothing here").unwrap(), Reply::Empty);
+    assert_eq!(repl.feed_line("$x = `").unwrap(), Reply::Incomplete);
+    assert!(repl.is_pending());
+    assert_eq!(repl.feed_line("k").unwrap(), Reply::Incomplete);
+    assert_eq!(
+        repl.feed_line("   `@i").unwrap(),
+        Reply::Defined("x".into())
+    );
+    // `@` reads from the same input each time, and the current character
+    // sticks around between lines.
+    assert_eq!(
+        repl.feed_line("``$x .a ?x").unwrap(),
+        Reply::Value(Func::Q('x'))
+    );
+    assert_eq!(repl.feed_line("``?xii").unwrap(), Reply::Value(Func::I));
+    assert_eq!(repl.feed_line("``?yii").unwrap(), Reply::Value(Func::V));
+    assert_eq!(
+        repl.feed_line("$x").unwrap(),
+        Reply::Value(Func::k1(Func::I))
+    );
+    assert_eq!(repl.feed_line("``?yii").unwrap(), Reply::Value(Func::I));
+    assert!(repl.feed_line("$nope").is_err());
+    assert!(!repl.is_pending());
+}
+
+#[test]
+fn step_limit() {
+    let mut opts = EvalOptions::default();
+    opts.max_steps = Some(10_000);
+    match eval_to_string_with("```sii``sii", Input::default(), opts.clone()) {
+        Err(EvalError::StepLimit(n)) => assert_eq!(n, 10_000),
+        other => panic!("expected a step limit error, got {:?}", other),
+    }
+    let out = eval_to_string_with("`r```s`k.*`kii", Input::default(), opts.clone());
+    assert_eq!(out.unwrap(), "*\n");
+
+    let mut ctx = unlambda::io::Ctx::with_options(std::io::empty(), Vec::new(), opts);
+    let e = parse_from_str("`r`.*i", Default::default()).unwrap();
+    ctx.execute(e).unwrap();
+    assert_eq!(ctx.steps(), 7);
+}
+
+#[test]
+fn timeout_and_cancel() {
+    use std::time::Duration;
+    let mut opts = EvalOptions::default();
+    opts.timeout = Some(Duration::from_millis(50));
+    match eval_to_string_with("```sii``sii", Input::default(), opts) {
+        Err(EvalError::TimedOut) => {}
+        other => panic!("expected a timeout, got {:?}", other),
+    }
+
+    let cancel = CancelHandle::new();
+    let mut opts = EvalOptions::default();
+    opts.cancel = Some(cancel.clone());
+    let thread =
+        std::thread::spawn(move || eval_to_string_with("```sii``sii", Input::default(), opts));
+    std::thread::sleep(Duration::from_millis(50));
+    cancel.cancel();
+    match thread.join().unwrap() {
+        Err(EvalError::Cancelled) => {}
+        other => panic!("expected cancellation, got {:?}", other),
+    }
+}
+
+#[test]
+fn object_limit() {
+    // Each recursive call is the operand of a `.a` that's still waiting for
+    // it, so the continuation grows forever.
+    let grows = "```s`k.a``sii``s`k.a``sii";
+    let mut opts = EvalOptions::default();
+    opts.max_objects = Some(1000);
+    match eval_to_string_with(grows, Input::default(), opts.clone()) {
+        Err(EvalError::ObjectLimit(n)) => assert!(n > 1000, "{}", n),
+        other => panic!("expected an object limit error, got {:?}", other),
+    }
+    // This one loops forever without needing more memory.
+    opts.max_steps = Some(100_000);
+    match eval_to_string_with("```sii``sii", Input::default(), opts.clone()) {
+        Err(EvalError::StepLimit(_)) => {}
+        other => panic!("expected a step limit error, got {:?}", other),
+    }
+}
+
+#[test]
+fn machine() {
+    use unlambda::{internals::*, io::Ctx};
+    let e = parse_from_str("`r`.a`.bi", Default::default()).unwrap();
+    let mut m = Machine::new(Ctx::new(std::io::empty(), Vec::new()), e);
+    assert!(m.step().unwrap());
+    assert_eq!(m.continuations().count(), 2);
+    assert!(matches!(m.continuations().last(), Some(Cont::Final)));
+    assert_eq!(m.run_for(6).unwrap(), 6);
+    assert!(!m.is_finished());
+    assert_eq!(m.run_for(100).unwrap(), 3);
+    assert!(m.is_finished());
+    assert!(!m.step().unwrap());
+    assert_eq!(m.result(), Some(&Func::I));
+    assert_eq!(m.continuations().count(), 0);
+    assert_eq!(m.ctx().steps(), 10);
+
+    // Running out of steps doesn't lose our place.
+    let mut opts = EvalOptions::default();
+    opts.max_steps = Some(5);
+    let e = parse_from_str("`r`.a`.bi", Default::default()).unwrap();
+    let mut m = Machine::new(Ctx::with_options(std::io::empty(), Vec::new(), opts), e);
+    assert!(matches!(m.run(), Err(EvalError::StepLimit(5))));
+    m.ctx_mut().options_mut().max_steps = None;
+    assert_eq!(m.run().unwrap(), &Func::I);
+    match m.ctx().output() {
+        unlambda::io::Output::Buffer(b) => assert_eq!(b, b"ba\n"),
+        _ => unreachable!(),
+    }
+
+    // Nor does failing partway through a step, e.g. at the output limit.
+    let mut opts = EvalOptions::default();
+    opts.max_output = Some(1);
+    let e = parse_from_str("`r`.a`.bi", Default::default()).unwrap();
+    let mut m = Machine::new(Ctx::with_options(std::io::empty(), Vec::new(), opts), e);
+    assert!(matches!(m.run(), Err(EvalError::OutputLimit(_))));
+    assert!(matches!(m.task(), Task::App(Func::Dot('a'), _, _)));
+    m.ctx_mut().options_mut().max_output = None;
+    assert_eq!(m.run().unwrap(), &Func::I);
+    match m.ctx().output() {
+        unlambda::io::Output::Buffer(b) => assert_eq!(b, b"ba\n"),
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn final_value() {
+    use unlambda::{internals::Func, io::Ctx};
+    let (v, out) = eval_value("`.a`.bi", Input::default()).unwrap();
+    assert_eq!((v, &out[..]), (Func::I, &b"ba"[..]));
+    // `e` exits right away, and the program's value is its argument.
+    let (v, out) = eval_value("`.a``e.zi", Input::default()).unwrap();
+    assert_eq!((v, &out[..]), (Func::Dot('z'), &b""[..]));
+
+    // Use a program as a function, this one applies its argument to `.y`.
+    let f = parse_from_str("``si`k.y", Default::default()).unwrap();
+    let mut ctx = Ctx::new(std::io::empty(), Vec::new());
+    let f = ctx.evaluate(f).unwrap();
+    assert_eq!(ctx.apply(f.clone(), Func::I).unwrap(), Func::Dot('y'));
+    assert_eq!(ctx.apply(f, Func::Dot('z')).unwrap(), Func::Dot('y'));
+    match ctx.output() {
+        unlambda::io::Output::Buffer(b) => assert_eq!(b, b"z"),
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn deep_nesting() {
+    const N: usize = 2_000_000;
+    // ````...`.aii...i, nested on the operator side.
+    let src = format!("{}.a{}", "`".repeat(N), "i".repeat(N));
+    assert_evals_to!(&*src, "a");
+    // `.a`.a`.a...i, nested on the operand side.
+    let src = format!("{}i", "`.a".repeat(N));
+    assert_eq!(eval_to_vec(&*src, Input::default()).unwrap().len(), N);
+
+    let e = parse_from_str(&"`".repeat(N), Default::default()).unwrap_err();
+    assert!(e.is_eof());
+
+    // None of these should overflow the stack when they're printed or dropped.
+    let src = format!("{}i{}", "`".repeat(N), "i".repeat(N));
+    let e = parse_from_str(&src, Default::default()).unwrap();
+    assert_eq!(e.to_string(), src);
+    drop(e);
+    let src = format!("{}i", "`ki".repeat(N));
+    drop(parse_from_str(&src, Default::default()).unwrap());
+    let src = format!("{}i", "`k".repeat(N));
+    let (value, _) = eval_value(&*src, Input::default()).unwrap();
+    assert_eq!(value.to_string(), format!("{}i", "`k".repeat(N)));
+    drop(value);
+    // Nor should applying a value that deep.
+    let src = format!("`{}ii", "`k".repeat(N));
+    let (value, _) = eval_value(&*src, Input::default()).unwrap();
+    assert!(matches!(value, unlambda::internals::Func::Op(_)));
+}
+
+#[test]
+fn reader_input() {
+    let cat = "``cd``d`@|`cd";
+    let out = eval_to_string(cat, Input::Bytes(b"caf\xc3\xa9 \xff!")).unwrap();
+    assert_eq!(out, "café \u{fffd}!");
+    let out = eval_to_string(cat, std::io::Cursor::new(b"from a cursor".to_vec())).unwrap();
+    assert_eq!(out, "from a cursor");
+    let out = eval_to_string(cat, &b"from a slice"[..]).unwrap();
+    assert_eq!(out, "from a slice");
+    assert_evals_to!(Input::Bytes(b"`.!i"), "!");
+    assert!(eval_to_string(Input::Bytes(b"`.!\xffi"), Input::Str("")).is_err());
+}
+
+#[test]
+fn output_sinks() {
+    let hello = "`.!`.d`.l`.r`.o`.w`. `.,`.o`.l`.l`.e`.Hi";
+    let mut buf = vec![];
+    eval_to_writer(hello, Input::Str(""), &mut buf).unwrap();
+    assert_eq!(buf, b"Hello, world!");
+
+    // The writer doesn't need to be `Send`, e.g. a locked stdout.
+    struct Shared(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+    impl std::io::Write for Shared {
+        fn write(&mut self, b: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(b)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+    let shared = std::rc::Rc::default();
+    eval_to_writer(hello, Input::Str(""), Shared(std::rc::Rc::clone(&shared))).unwrap();
+    assert_eq!(*shared.borrow(), b"Hello, world!");
+    let _ = || eval_to_writer("i", Input::Str(""), std::io::stdout().lock());
+
+    let mut chars = vec![];
+    let out = io::Output::callback(|c| {
+        chars.push(c);
+        Ok(())
+    });
+    let mut ctx = io::Ctx::new(&b""[..], out);
+    ctx.execute(parse_from_str(hello, Default::default()).unwrap())
+        .unwrap();
+    drop(ctx);
+    assert_eq!(chars.iter().collect::<String>(), "Hello, world!");
+
+    // Errors from the callback stop the program.
+    let out = io::Output::callback(|c| match c {
+        'w' => Err(std::io::Error::other("no w's")),
+        _ => Ok(()),
+    });
+    let mut ctx = io::Ctx::new(&b""[..], out);
+    let res = ctx.execute(parse_from_str(hello, Default::default()).unwrap());
+    assert!(matches!(res, Err(EvalError::Io(_))));
+}
+
+#[test]
+fn latin1() {
+    let mut opts = EvalOptions::default();
+    opts.encoding = Encoding::Latin1;
+    let cat = "``cd``d`@|`cd";
+    let data = b"\x89PNG\r\n\x1a\n\0\xff\xc3\xa9";
+    let out = eval_to_vec_with(cat, &data[..], opts.clone()).unwrap();
+    assert_eq!(out, data);
+    // Each byte is one character, comparable with `?`.
+    let is_e_acute = "``@i```?éi.yi";
+    let out = eval_to_vec_with(is_e_acute, &b"\xe9"[..], opts.clone()).unwrap();
+    assert_eq!(out, b"y");
+    let out = eval_to_vec(is_e_acute, &b"\xe9"[..]).unwrap();
+    assert_eq!(out, b"");
+    // Characters past U+00FF are still written as UTF-8.
+    let out = eval_to_vec_with("`.é`.λi", Input::Str(""), opts).unwrap();
+    assert_eq!(out, "λ".bytes().chain(Some(0xe9)).collect::<Vec<_>>());
+}
+
+#[test]
+fn invalid_utf8() {
+    let cat = "``cd``d`@|`cd";
+    let run = |policy, input: &[u8]| {
+        let mut opts = EvalOptions::default();
+        opts.invalid_utf8 = policy;
+        eval_to_string_with(cat, input, opts)
+    };
+    let input = b"a\xffb\xc3(c\xe2\x82";
+    // A truncated sequence takes the `(` with it, as it always has.
+    assert_eq!(
+        run(Utf8Policy::Replace, input).unwrap(),
+        "a\u{fffd}b\u{fffd}c\u{fffd}"
+    );
+    assert_eq!(
+        run(Utf8Policy::Replace, b"\xe2\x82(\xe2(ab").unwrap(),
+        "\u{fffd}\u{fffd}b"
+    );
+    assert_eq!(run(Utf8Policy::Skip, input).unwrap(), "ab(c");
+    assert!(matches!(
+        run(Utf8Policy::Error, input),
+        Err(EvalError::InvalidUtf8(1))
+    ));
+    assert!(matches!(
+        run(Utf8Policy::Error, b"ab\xc3(c"),
+        Err(EvalError::InvalidUtf8(2))
+    ));
+    assert!(matches!(
+        run(Utf8Policy::Error, b"\xc3\xa9\xf0\x9f\x98\x80\xc0\x80"),
+        Err(EvalError::InvalidUtf8(6))
+    ));
+    assert_eq!(run(Utf8Policy::Error, "héllo".as_bytes()).unwrap(), "héllo");
+}
+
+#[test]
+fn output_limit() {
+    let mut opts = EvalOptions::default();
+    opts.max_output = Some(5);
+    let forever = "```sii``s.a``sii";
+    match eval_to_string_with(forever, Input::Str(""), opts.clone()) {
+        Err(EvalError::OutputLimit(out)) => assert_eq!(out, b"aaaaa"),
+        other => panic!("expected output limit, got {:?}", other),
+    }
+    // Exactly at the limit is fine.
+    let out = eval_to_string_with("`.a`.a`.a`.a`.ai", Input::Str(""), opts.clone()).unwrap();
+    assert_eq!(out, "aaaaa");
+    let mut buf = vec![];
+    let res = eval_to_writer_with(forever, Input::Str(""), &mut buf, opts.clone());
+    assert!(matches!(res, Err(EvalError::OutputLimit(v)) if v.is_empty()));
+    assert_eq!(buf, b"aaaaa");
+
+    // Writes that fail don't count towards it.
+    let mut fail = true;
+    let out = io::Output::callback(move |_| match std::mem::take(&mut fail) {
+        true => Err(std::io::ErrorKind::Interrupted.into()),
+        false => Ok(()),
+    });
+    opts.max_output = Some(2);
+    let e = parse_from_str("`.a`.ai", Default::default()).unwrap();
+    let mut m = Machine::new(io::Ctx::with_options(std::io::empty(), out, opts), e);
+    assert!(matches!(m.run(), Err(EvalError::Io(_))));
+    m.run().unwrap();
+}
+
+#[test]
+fn async_eval() {
+    use std::{
+        future::Future,
+        pin::Pin,
+        sync::Arc,
+        task::{Context, Poll, Wake},
+    };
+    use unlambda::future::*;
+
+    struct Noop;
+    impl Wake for Noop {
+        fn wake(self: Arc<Self>) {}
+    }
+    fn block_on<F: Future + Unpin>(mut f: F) -> (F::Output, usize) {
+        let waker = Arc::new(Noop).into();
+        let mut cx = Context::from_waker(&waker);
+        let mut pending = 0;
+        loop {
+            match Pin::new(&mut f).poll(&mut cx) {
+                Poll::Ready(v) => return (v, pending),
+                Poll::Pending => pending += 1,
+            }
+        }
+    }
+
+    // Only has a byte ready every other time it's polled.
+    struct Trickle<'a>(&'a [u8], bool);
+    impl AsyncInput for Trickle<'_> {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<std::io::Result<usize>> {
+            self.1 = !self.1;
+            if self.1 {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            let n = self.0.len().min(buf.len()).min(1);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Poll::Ready(Ok(n))
+        }
+    }
+
+    let cat = parse_from_str("``cd``d`@|`cd", Default::default()).unwrap();
+    let mut out = vec![];
+    let input = Trickle("héllo".as_bytes(), false);
+    let f = future::eval_async(cat, input, &mut out, EvalOptions::default());
+    let (res, pending) = block_on(f);
+    assert!(res.is_ok());
+    assert!(pending > 0);
+    assert_eq!(out, "héllo".as_bytes());
+
+    let e = parse_from_str("```sii``sii", Default::default()).unwrap();
+    let mut opts = EvalOptions::default();
+    opts.max_steps = Some(5000);
+    let (res, pending) = block_on(future::eval_async(e, &b""[..], vec![], opts));
+    assert!(matches!(res, Err(EvalError::StepLimit(5000))));
+    // It yielded while running.
+    assert!(pending > 0);
+
+    // The IO policy applies here too: none of the real input gets read.
+    let mut input = &b"real input"[..];
+    for io in [IoPolicy::Deny, IoPolicy::Virtual] {
+        let e = parse_from_str("`@i", Default::default()).unwrap();
+        let mut opts = EvalOptions::default();
+        opts.io = io;
+        let (res, _) = block_on(future::eval_async(e, &mut input, vec![], opts));
+        assert_eq!(res.is_ok(), io == IoPolicy::Virtual);
+    }
+    assert_eq!(input, b"real input");
+}
+
+#[test]
+fn transcript() {
+    use unlambda::{io::Ctx, transcript::*};
+    let cat = "``cd``d`@|`cd";
+    let run = |src: &str, input: &'static str, t: Option<Transcript>| {
+        let mut ctx = Ctx::new(input.as_bytes(), Vec::new());
+        match t {
+            Some(t) => ctx.replay(t),
+            None => ctx.record(),
+        }
+        let res = ctx
+            .execute(parse_from_str(src, Default::default()).unwrap())
+            .and_then(|()| ctx.finish_replay());
+        (res, ctx.take_transcript())
+    };
+
+    let (res, t) = run(cat, "a'\u{301}", None);
+    res.unwrap();
+    let t = t.unwrap();
+    let text = t.to_string();
+    assert_eq!(
+        text,
+        "# unlambda transcript\n\
+         20 in 'a'\n\
+         22 out 'a'\n\
+         40 in '\\''\n\
+         42 out '\\''\n\
+         51 in '\\u{301}'\n\
+         53 out '\\u{301}'\n\
+         71 in eof\n\
+         82 in eof\n\
+         93 in eof\n\
+         113 in eof\n",
+    );
+    assert_eq!(text.parse::<Transcript>(), Ok(t.clone()));
+    assert_eq!("1 in\n".parse::<Transcript>(), Err(TranscriptError(1)));
+
+    // Replaying ignores the real input.
+    let (res, _) = run(cat, "", Some(t.clone()));
+    res.unwrap();
+
+    // Prints something else.
+    let (res, _) = run("`.x`@i", "", Some(t.clone()));
+    match res {
+        Err(EvalError::Diverged(d)) => {
+            assert_eq!(d.expected, Some((22, Event::Write('a'))));
+            assert_eq!(d.found, Action::Write('x'));
+        }
+        other => panic!("expected divergence, got {:?}", other),
+    }
+
+    // Stops early.
+    let (res, _) = run("`@i", "", Some(t));
+    match res {
+        Err(EvalError::Diverged(d)) => {
+            assert_eq!(d.expected, Some((22, Event::Write('a'))));
+            assert_eq!(d.found, Action::End);
+        }
+        other => panic!("expected divergence, got {:?}", other),
+    }
+}
+
+#[test]
+fn io_policy() {
+    use unlambda::{internals::Func, io::Ctx};
+    let mut opts = EvalOptions::default();
+    opts.io = IoPolicy::Deny;
+    // Pure programs are fine, even if they pass IO builtins around, and give
+    // the same values as they would otherwise.
+    let (value, out) = eval_value_with("``k.xr", Input::Str(""), opts.clone()).unwrap();
+    assert_eq!((value, out), (Func::Dot('x'), vec![]));
+    let (value, _) = eval_value_with("`k.x", Input::Str(""), opts.clone()).unwrap();
+    assert_eq!(value, Func::k1(Func::Dot('x')));
+    for (src, builtin) in [
+        ("`.xi", ".x"),
+        ("`ri", "r"),
+        ("`@i", "@"),
+        ("`?xi", "?x"),
+        ("`|i", "|"),
+    ] {
+        match eval_to_string_with(src, Input::Str(""), opts.clone()) {
+            Err(EvalError::IoNotPermitted(e)) => {
+                assert_eq!(e.builtin, builtin);
+                // It says where the builtin is.
+                assert_eq!(e.span.map(|s| (s.line, s.col)), Some((1, 2)));
+            }
+            other => panic!("{:?} should fail, got {:?}", src, other),
+        }
+    }
+
+    opts.io = IoPolicy::Virtual;
+    let cat = parse_from_str("``cd``d`@|`cd", Default::default()).unwrap();
+    let mut real_out = vec![];
+    let mut ctx = Ctx::with_options(&b"real input"[..], &mut real_out, opts.clone());
+    ctx.device_mut().input = b"virtual input".to_vec();
+    ctx.execute(cat).unwrap();
+    assert_eq!(ctx.device().output, b"virtual input");
+    drop(ctx);
+    assert!(real_out.is_empty());
+
+    // An output iterator produces what the program writes to the device.
+    for encoding in [Encoding::Utf8, Encoding::Latin1] {
+        opts.encoding = encoding;
+        let chars = eval_chars("`.λ`.é`.ai", Input::Str(""), opts.clone()).unwrap();
+        assert_eq!(chars.collect::<Result<String, _>>().unwrap(), "aéλ");
+    }
+}
+
+#[test]
+fn output_chars() {
+    // Prints `a` forever.
+    let forever = "```sii``s.a``sii";
+    let chars = eval_chars(forever, Input::Str(""), EvalOptions::default()).unwrap();
+    let s = chars.take(100).collect::<Result<String, _>>().unwrap();
+    assert_eq!(s, "a".repeat(100));
+
+    let mut chars = eval_chars(
+        "`.!`.d`.l`.r`.o`.w`. `.,`.o`.l`.l`.e`.Hi",
+        Input::Str(""),
+        EvalOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(
+        chars
+            .find(|c| matches!(c, Ok(',') | Err(_)))
+            .unwrap()
+            .unwrap(),
+        ','
+    );
+    assert!(!chars.machine().is_finished());
+    let rest = chars.by_ref().collect::<Result<String, _>>().unwrap();
+    assert_eq!(rest, " world!");
+    assert!(chars.next().is_none());
+
+    // Errors end the iterator.
+    let mut opts = EvalOptions::default();
+    opts.max_steps = Some(100);
+    let items = eval_chars(forever, Input::Str(""), opts)
+        .unwrap()
+        .collect::<Vec<_>>();
+    assert!(items.len() > 1);
+    assert!(matches!(items.last(), Some(Err(EvalError::StepLimit(100)))));
+    assert!(items[..items.len() - 1]
+        .iter()
+        .all(|c| matches!(c, Ok('a'))));
+}
+
+#[test]
+fn session() {
+    use unlambda::io::Ctx;
+    let parse = |s: &str| parse_from_str(s, Default::default()).unwrap();
+    let head = parse("``@i`|i");
+    let print_last = parse("`|i");
+    let cat = parse("``cd``d`@|`cd");
+
+    let mut out = vec![];
+    let mut s = Session::new(Ctx::new(&b"hello"[..], &mut out));
+    s.run_all(vec![head.clone(), print_last.clone(), cat.clone()])
+        .unwrap();
+    assert_eq!(s.last_char(), None);
+    drop(s);
+    assert_eq!(out, b"hhello");
+
+    let mut out = vec![];
+    let mut s = Session::new(Ctx::new(&b"hello"[..], &mut out));
+    s.set_reset_last_char(true);
+    s.run_all(vec![head.clone(), print_last.clone(), cat])
+        .unwrap();
+    s.set_reset_last_char(false);
+    s.set_last_char(Some('!'));
+    s.run(print_last).unwrap();
+    drop(s);
+    assert_eq!(out, b"hello!");
+}
+
+#[test]
+fn pipeline() {
+    use unlambda::pipeline::Pipeline;
+    let parse = |s: &str| parse_from_str(s, Default::default()).unwrap();
+    let cat = parse("``cd``d`@|`cd");
+    let yes = parse("```sii``s.y``sii");
+    let head1 = "``@i`|i";
+    let head3 = parse(&format!("`{}`{}{}", head1, head1, head1));
+
+    let mut out = vec![];
+    let stages = vec![cat.clone(), cat.clone(), cat.clone()];
+    Pipeline::new("héllo".as_bytes(), &mut out, stages, EvalOptions::default())
+        .run()
+        .unwrap();
+    assert_eq!(out, "héllo".as_bytes());
+
+    let mut out = vec![];
+    let stages = vec![cat.clone(), head3.clone()];
+    Pipeline::new(&b"hello"[..], &mut out, stages, EvalOptions::default())
+        .run()
+        .unwrap();
+    assert_eq!(out, b"hel");
+
+    // `yes` never finishes, but `head` does.
+    let mut out = vec![];
+    let mut p = Pipeline::new(
+        &b""[..],
+        &mut out,
+        vec![yes, cat, head3],
+        EvalOptions::default(),
+    );
+    p.run().unwrap();
+    assert!(!p.stages()[0].is_finished());
+    drop(p);
+    assert_eq!(out, b"yyy");
+}
+
+#[test]
+fn spans() {
+    use unlambda::{
+        internals::Expr,
+        io::Ctx,
+        parse::{ParseOptions, Span},
+    };
+    let span = |start, end, line, col| {
+        Some(Span {
+            start,
+            end,
+            line,
+            col,
+        })
+    };
+    let (e, map) = parse_with_spans("```s.x\n  `ki # hi\n  i", Default::default()).unwrap();
+    assert_eq!(map.len(), 9);
+    assert_eq!(map.get(&e), span(0, 21, 1, 1));
+    let (a, i) = match &*e {
+        Expr::App(a, i) => (a, i),
+        _ => unreachable!(),
+    };
+    assert_eq!(map.get(i), span(20, 21, 3, 3));
+    let (b, ki) = match &**a {
+        Expr::App(b, ki) => (b, ki),
+        _ => unreachable!(),
+    };
+    assert_eq!(map.get(a), span(1, 12, 1, 2));
+    assert_eq!(map.get(ki), span(9, 12, 2, 3));
+    match &**b {
+        Expr::App(s, x) => {
+            assert_eq!(map.get(b), span(2, 6, 1, 3));
+            assert_eq!(map.get(s), span(3, 4, 1, 4));
+            assert_eq!(map.get(x), span(4, 6, 1, 5));
+        }
+        _ => unreachable!(),
+    }
+    // Columns count chars, not bytes.
+    let (e, map) = parse_with_spans("`.λ.x", Default::default()).unwrap();
+    match &*e {
+        Expr::App(_, x) => assert_eq!(map.get(x), span(4, 6, 1, 4)),
+        _ => unreachable!(),
+    }
+    // Nodes that weren't parsed don't have spans.
+    assert_eq!(map.get(&Expr::Func(internals::Func::I)), None);
+
+    // IO errors can say where the IO happened.
+    let mut opts = EvalOptions::default();
+    opts.io = IoPolicy::Deny;
+    let mut parse_opts = ParseOptions::default();
+    parse_opts.locate_io = true;
+    let (e, map) = parse_with_spans("``ki\n`.yi", parse_opts.clone()).unwrap();
+    assert_eq!(map.len(), 7);
+    let mut ctx = Ctx::with_options(&b""[..], vec![], opts.clone());
+    match ctx.execute(e) {
+        Err(EvalError::IoNotPermitted(e)) => {
+            assert_eq!(e.span, span(6, 8, 2, 2));
+            assert!(e.to_string().ends_with("(line 2, column 2)"));
+        }
+        other => panic!("expected an IO error, got {:?}", other),
+    }
+    // The span is that of the builtin that's actually applied, even if the
+    // same one appears later.
+    let e = parse_from_str("```k.x.xi", parse_opts.clone()).unwrap();
+    match ctx.execute(e) {
+        Err(EvalError::IoNotPermitted(e)) => assert_eq!(e.span, span(4, 6, 1, 5)),
+        other => panic!("expected an IO error, got {:?}", other),
+    }
+    // Otherwise they behave like any other builtin.
+    let e = parse_from_str("`.a`@i", parse_opts).unwrap();
+    let mut ctx = Ctx::with_options(&b"x"[..], vec![], EvalOptions::default());
+    ctx.execute(e).unwrap();
+    assert!(matches!(ctx.output(), unlambda::io::Output::Buffer(b) if b == b"a"));
+}
+
+#[test]
+fn parse_diagnostics() {
+    use unlambda::parse::{Expected, ParseOptions};
+    let mut strict = ParseOptions::default();
+    strict.strict = true;
+    let err = |src: &str| parse_from_str(src, strict.clone()).unwrap_err();
+
+    let src = "# comment\n``s\n  `k  x\n";
+    let e = err(src);
+    assert_eq!(e.line_col(), (3, 7));
+    assert_eq!(e.offset(), Some(20));
+    assert_eq!(e.expected(), Some(Expected::Operand));
+    assert_eq!(e.open_backtick(), Some(16));
+    assert_eq!(
+        e.to_string(),
+        "unexpected character 'x' at line 3, column 7"
+    );
+    assert_eq!(
+        e.render(src),
+        "error: unexpected character 'x'\n\
+         \x20--> <input>:3:7\n\
+         \x20 |\n\
+         3 |   `k  x\n\
+         \x20 |   -   ^ operand expected after `\n\
+         \x20 |   this ` is missing its operand\n",
+    );
+
+    let src = "``s\n\n`";
+    let e = err(src);
+    assert_eq!(e.line_col(), (3, 2));
+    assert_eq!(e.expected(), Some(Expected::Operator));
+    assert_eq!(
+        e.render(src),
+        "error: unexpected end of input\n\
+         \x20--> <input>:3:2\n\
+         \x20 |\n\
+         3 | `\n\
+         \x20 | -^ operator expected after `\n\
+         \x20 | this ` is missing its operator and operand\n",
+    );
+
+    let src = "`.λi\n λ";
+    let e = err(src);
+    assert_eq!(e.line_col(), (2, 2));
+    assert_eq!(e.expected(), Some(Expected::End));
+    assert_eq!(e.open_backtick(), None);
+    assert_eq!(
+        e.render(src),
+        "error: unexpected character 'λ'\n\
+         \x20--> <input>:2:2\n\
+         \x20 |\n\
+         2 |  λ\n\
+         \x20 |  ^ expected the end of the input\n",
+    );
+
+    // Tabs in the line are kept, so the markers line up with it.
+    let src = "\t`\tx";
+    assert_eq!(
+        err(src).render(src),
+        "error: unexpected character 'x'\n\
+         \x20--> <input>:1:4\n\
+         \x20 |\n\
+         1 | \t`\tx\n\
+         \x20 | \t-\t^ operator expected after `\n\
+         \x20 | \tthis ` is missing its operator and operand\n",
+    );
+}
+
+#[test]
+fn parse_recovering() {
+    use unlambda::parse::{Expected, ParseErrorKind, ParseOptions};
+    let mut strict = ParseOptions::default();
+    strict.strict = true;
+
+    let (expr, errors) = unlambda::parse_recovering("```si%k\n`x.", strict.clone());
+    assert_eq!(expr.to_string(), "```sik`vv");
+    let found = errors
+        .iter()
+        .map(|e| (e.line_col(), e.expected()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        found,
+        [
+            ((1, 6), Some(Expected::Operand)),
+            ((2, 2), Some(Expected::Operator)),
+            ((2, 4), Some(Expected::CharAfter('.'))),
+        ]
+    );
+    assert!(matches!(
+        errors[0].kind(),
+        ParseErrorKind::UnexpectedChar('%')
+    ));
+    assert!(errors[2].is_eof());
+
+    // Nothing at all is just a placeholder.
+    let (expr, errors) = unlambda::parse_recovering("  # nothing", strict.clone());
+    assert_eq!(expr.to_string(), "v");
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].expected(), Some(Expected::Expr));
+
+    // Trailing garbage is only an error when strict.
+    let (expr, errors) = unlambda::parse_recovering("`ii i", strict);
+    assert_eq!(expr.to_string(), "`ii");
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].expected(), Some(Expected::End));
+    let (_, errors) = unlambda::parse_recovering("`ii i", Default::default());
+    assert!(errors.is_empty());
+
+    // A `.` at the very end is missing its char, not an operator.
+    let (expr, errors) = unlambda::parse_recovering("`.", Default::default());
+    assert_eq!(expr.to_string(), "`vv");
+    let found = errors
+        .iter()
+        .map(|e| (e.line_col(), e.expected()))
+        .collect::<Vec<_>>();
+    assert_eq!(found, [((1, 3), Some(Expected::CharAfter('.')))]);
+    assert_eq!(
+        errors[0].to_string(),
+        "unexpected end of input at line 1, column 3"
+    );
+    let e = parse_from_str("?", Default::default()).unwrap_err();
+    assert_eq!(e.expected(), Some(Expected::CharAfter('?')));
+
+    let src = "`.a`.b`.ci";
+    let (expr, errors) = unlambda::parse_recovering(src, Default::default());
+    assert!(errors.is_empty());
+    assert_eq!(expr, parse_from_str(src, Default::default()).unwrap());
+}
+
+#[test]
+fn lossless_syntax_tree() {
+    use unlambda::cst::Trivia;
+    use unlambda::parse::ParseOptions;
+    let quine = include_str!("fixtures/quine10.unl");
+    let tree = unlambda::parse_lossless(quine, Default::default()).unwrap();
+    assert_eq!(tree.to_string(), quine);
+    assert_eq!(
+        tree.to_expr(),
+        parse_from_str(quine, Default::default()).unwrap()
+    );
+    assert!(tree
+        .tokens()
+        .iter()
+        .flat_map(|t| t.leading())
+        .any(|t| t.text() == "# unlambda quines RULE ! --- MXK ---"));
+
+    let src = "# apply\n` .# # a dot\n\tK  # done\n";
+    let mut tree = unlambda::parse_lossless(src, Default::default()).unwrap();
+    assert_eq!(tree.to_string(), src);
+    let texts = tree.tokens().iter().map(|t| t.text()).collect::<Vec<_>>();
+    assert_eq!(texts, ["`", ".#", "K"]);
+    let (operator, operand) = tree.root().children().unwrap();
+    assert_eq!(operator.token().text(), ".#");
+    assert_eq!(operand.to_expr().to_string(), "k");
+    assert!(operand.children().is_none());
+    assert_eq!(tree.tokens()[1].leading(), [Trivia::Whitespace(" ".into())]);
+    assert_eq!(
+        tree.trailing(),
+        [
+            Trivia::Whitespace("  ".into()),
+            Trivia::Comment("# done".into()),
+            Trivia::Whitespace("\n".into()),
+        ]
+    );
+
+    // Rewriting the trivia keeps the comments.
+    for i in 0..tree.tokens().len() {
+        let comments = tree.tokens()[i]
+            .leading()
+            .iter()
+            .filter(|t| matches!(t, Trivia::Comment(_)))
+            .flat_map(|c| [c.clone(), Trivia::Whitespace("\n".into())])
+            .collect();
+        tree.set_leading(i, comments).unwrap();
+    }
+    tree.set_trailing(vec![]).unwrap();
+    assert_eq!(tree.to_string(), "# apply\n`.## a dot\nK");
+    assert_eq!(
+        parse_from_str(&tree.to_string(), Default::default()).unwrap(),
+        tree.to_expr()
+    );
+    // But not if that would change the program.
+    let comment = Trivia::Comment("# x".into());
+    assert!(tree.set_leading(2, vec![comment.clone()]).is_err());
+    let spaced = vec![comment.clone(), Trivia::Whitespace(" ".into())];
+    assert!(tree.set_leading(2, spaced).is_err());
+    for bad in [Trivia::Whitespace("i".into()), Trivia::Skipped("i".into())] {
+        assert!(tree.set_leading(0, vec![bad]).is_err());
+    }
+    assert!(tree.set_trailing(vec![comment]).is_ok());
+    assert_eq!(tree.to_string(), "# apply\n`.## a dot\nK# x");
+
+    // Non-strict parsing keeps whatever was ignored.
+    let tree = unlambda::parse_lossless("`ii `%k # x", Default::default()).unwrap();
+    assert_eq!(tree.to_string(), "`ii `%k # x");
+    assert_eq!(tree.trailing()[1], Trivia::Skipped("`%k".into()));
+    let mut strict = ParseOptions::default();
+    strict.strict = true;
+    assert!(unlambda::parse_lossless("`ii `%k", strict).is_err());
+
+    // From an `Expr`, and deeply nested.
+    let deep = format!("{}i{}", "`".repeat(100_000), "k".repeat(100_000));
+    let expr = parse_from_str(&deep, Default::default()).unwrap();
+    let tree = unlambda::SyntaxTree::from(&*expr);
+    assert_eq!(tree.to_string(), deep);
+    // (`Expr`'s `PartialEq` would recurse too deeply here.)
+    assert_eq!(unlambda::SyntaxTree::from(&*tree.to_expr()), tree);
+}
+
+#[test]
+fn tokenize() {
+    use unlambda::lex::{tokenize, TokenKind};
+    let src = "`.#K # hi\n`?\n%.";
+    let tokens = tokenize(src)
+        .map(|t| (t.kind, &src[t.range]))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        tokens,
+        [
+            (TokenKind::Backtick, "`"),
+            (TokenKind::Dot('#'), ".#"),
+            (TokenKind::Builtin('K'), "K"),
+            (TokenKind::Whitespace, " "),
+            (TokenKind::Comment, "# hi"),
+            (TokenKind::Whitespace, "\n"),
+            (TokenKind::Backtick, "`"),
+            (TokenKind::Question('\n'), "?\n"),
+            (TokenKind::Invalid, "%"),
+            (TokenKind::Invalid, "."),
+        ]
+    );
+    assert_eq!(
+        TokenKind::Builtin('K').func(),
+        Some(unlambda::internals::Func::K)
+    );
+    assert_eq!(TokenKind::Dot('#').func().unwrap().to_string(), ".#");
+    assert!(TokenKind::Comment.is_trivia());
+    assert!(TokenKind::Comment.func().is_none());
+
+    // The tokens cover everything, even with multi-byte chars.
+    let src = "`.λ λ  i";
+    let mut end = 0;
+    for t in tokenize(src) {
+        assert_eq!(t.range.start, end);
+        end = t.range.end;
+    }
+    assert_eq!(end, src.len());
+}
+
+#[test]
+fn ctx_is_send() {
+    // A ctx can be set up on one thread and used on another.
+    let mut ctx = io::Ctx::new(&b"x"[..], vec![]);
+    ctx.record();
+    let ctx = std::thread::spawn(move || {
+        let e = parse_from_str("`.a`@i", Default::default()).unwrap();
+        ctx.execute(e).unwrap();
+        ctx
+    })
+    .join()
+    .unwrap();
+    assert!(matches!(ctx.output(), io::Output::Buffer(b) if b == b"a"));
+}